@@ -0,0 +1,48 @@
+//! Times the scripts in `benches/scripts` under both backends.
+//!
+//! Run with `cargo bench`; each script runs a few times and the fastest
+//! wall-clock time is reported, which filters out process start-up noise.
+
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+const RUNS: usize = 5;
+
+fn time(args: &[&str]) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let status = Command::new(env!("CARGO_BIN_EXE_rlox"))
+                .args(args)
+                .stdout(std::process::Stdio::null())
+                .status()
+                .expect("failed to run rlox");
+            assert!(status.success(), "rlox {args:?} failed");
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let scripts = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/scripts");
+    let mut paths: Vec<_> = fs::read_dir(scripts)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+
+    println!("{:<24} {:>12} {:>12}", "script", "tree walker", "vm");
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let path = path.to_str().unwrap();
+
+        let walker = time(&[path]);
+        let vm = time(&["--vm", path]);
+        println!("{name:<24} {walker:>12.2?} {vm:>12.2?}");
+    }
+}
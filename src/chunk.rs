@@ -0,0 +1,243 @@
+use crate::token::LiteralValue;
+
+/// Instructions understood by the VM.
+///
+/// Operands follow the opcode byte. `u16` operands (constant indices and
+/// jump offsets) are stored big-endian; local slots and counts that cannot
+/// exceed a byte are a single `u8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// `u16` constant index.
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+
+    /// `u16` constant index of the variable name.
+    GetGlobal,
+    DefineGlobal,
+    DefineConstGlobal,
+    SetGlobal,
+
+    /// `u8` stack slot relative to the frame.
+    GetLocal,
+    SetLocal,
+
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+    Negate,
+    Not,
+    /// Fails unless the top of the stack is a boolean; used by `and`/`or`.
+    CheckBool,
+
+    Print,
+
+    /// `u16` forward offset.
+    Jump,
+    /// `u16` forward offset, taken unless the top of the stack is `true`.
+    /// The condition is left on the stack.
+    JumpIfFalse,
+    /// `u16` backward offset.
+    Loop,
+
+    /// `u16` element count.
+    BuildList,
+    /// `u16` entry count; keys and values are interleaved on the stack.
+    BuildMap,
+    Range,
+    RangeInclusive,
+    Index,
+    SetIndex,
+
+    /// `u16` constant index of the method name, then a `u8` argument count.
+    Invoke,
+    /// `u16` constant index of the native's name, then a `u8` argument count.
+    CallNative,
+    /// `u16` constant index of the property name; always a runtime error
+    /// since only method calls are supported.
+    GetProperty,
+    /// `u8` argument count; always a runtime error since nothing but
+    /// methods can be called.
+    Call,
+
+    /// Replaces the iterable on top of the stack with an iterator.
+    IterStart,
+    /// `u16` forward offset. Pushes the next value, or drops the iterator
+    /// and jumps once it is exhausted.
+    IterNext,
+
+    /// `u16` forward offset to a `catch` clause. Until the matching
+    /// `PopHandler`, a catchable error unwinds to it with the caught value
+    /// on the stack.
+    PushCatch,
+    /// `u16` forward offset to the copy of a `finally` clause run while
+    /// unwinding; the error is set aside until `Rethrow`.
+    PushFinally,
+    PopHandler,
+    /// Raises the value on top of the stack.
+    Throw,
+    /// Resumes the error set aside by the innermost `PushFinally`.
+    Rethrow,
+
+    Return,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 46] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::DefineConstGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Negate,
+        OpCode::Not,
+        OpCode::CheckBool,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::BuildList,
+        OpCode::BuildMap,
+        OpCode::Range,
+        OpCode::RangeInclusive,
+        OpCode::Index,
+        OpCode::SetIndex,
+        OpCode::Invoke,
+        OpCode::CallNative,
+        OpCode::GetProperty,
+        OpCode::Call,
+        OpCode::IterStart,
+        OpCode::IterNext,
+        OpCode::PushCatch,
+        OpCode::PushFinally,
+        OpCode::PopHandler,
+        OpCode::Throw,
+        OpCode::Rethrow,
+        OpCode::Return,
+    ];
+}
+
+impl OpCode {
+    /// Number of operand bytes following the opcode.
+    pub fn operand_width(self) -> usize {
+        match self {
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::Call => 1,
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::BuildList
+            | OpCode::BuildMap
+            | OpCode::IterNext
+            | OpCode::PushCatch
+            | OpCode::PushFinally => 2,
+            OpCode::Invoke | OpCode::CallNative => 3,
+            _ => 0,
+        }
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// A compiled program: bytecode plus the constants and source lines it
+/// refers to.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<LiteralValue>,
+    /// Run-length encoded: `(offset, line)` marks where a new source line
+    /// starts, in increasing offset order.
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, line: usize) {
+        if self.lines.last().is_none_or(|&(_, last)| last != line) {
+            self.lines.push((self.code.len(), line));
+        }
+
+        self.code.push(byte);
+    }
+
+    pub fn add_constant(&mut self, value: LiteralValue) -> usize {
+        if let Some(index) = self.constants.iter().position(|c| *c == value) {
+            return index;
+        }
+
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Source line of the instruction at `offset`.
+    pub fn line(&self, offset: usize) -> usize {
+        let run = self.lines.partition_point(|&(start, _)| start <= offset);
+        run.checked_sub(1).map_or(0, |run| self.lines[run].1)
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chunk, OpCode};
+
+    #[test]
+    fn opcodes_round_trip() {
+        for (byte, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*op as u8, byte as u8);
+            assert_eq!(OpCode::try_from(byte as u8), Ok(*op));
+        }
+        assert!(OpCode::try_from(OpCode::ALL.len() as u8).is_err());
+    }
+
+    #[test]
+    fn line_table() {
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::Nil as u8, 1);
+        chunk.write(OpCode::Nil as u8, 1);
+        chunk.write(OpCode::Print as u8, 3);
+
+        assert_eq!(chunk.line(0), 1);
+        assert_eq!(chunk.line(1), 1);
+        assert_eq!(chunk.line(2), 3);
+    }
+}
@@ -0,0 +1,541 @@
+use crate::{
+    chunk::{Chunk, OpCode},
+    expr::Expr,
+    intern::Symbol,
+    native,
+    parser::Stmt,
+    token::{LiteralValue, Token},
+    token_type::TokenType,
+    ErrorMsg,
+};
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+}
+
+/// Lowers a resolved program into a single bytecode `Chunk`.
+///
+/// Names declared at the top level are globals, looked up by name at
+/// runtime. Everything declared inside a block or loop is a local: it lives
+/// in a VM stack slot that is fixed here, at compile time.
+pub struct Compiler {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // Line of the most recent token seen; stamped on every emitted byte.
+    line: usize,
+    had_error: bool,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            chunk: Chunk::default(),
+            locals: vec![],
+            scope_depth: 0,
+            line: 1,
+            had_error: false,
+        }
+    }
+
+    /// Returns `None` if the program exceeds one of the bytecode limits.
+    pub fn compile(mut self, stmts: &[Stmt]) -> Option<Chunk> {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.emit(OpCode::Return);
+
+        (!self.had_error).then_some(self.chunk)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expr(expr);
+                self.emit(OpCode::Pop);
+            }
+
+            Stmt::Print(expr) => {
+                self.expr(expr);
+                self.emit(OpCode::Print);
+            }
+
+            Stmt::Variable { keyword, bindings } => {
+                for binding in bindings {
+                    match &binding.initializer {
+                        Some(initializer) => self.expr(initializer),
+                        None => self.emit(OpCode::Nil),
+                    }
+
+                    self.line = binding.token.line;
+
+                    if self.scope_depth > 0 {
+                        // The initializer's value is already in the new slot.
+                        // Constant locals need no runtime check: the
+                        // resolver rejects every assignment to them.
+                        self.add_local(&binding.token);
+                    } else if keyword.token_type == TokenType::Var {
+                        self.emit_name(OpCode::DefineGlobal, &binding.token);
+                    } else {
+                        self.emit_name(OpCode::DefineConstGlobal, &binding.token);
+                    }
+                }
+            }
+
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+                self.end_scope();
+            }
+
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.nested(then_branch);
+
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.nested(else_branch);
+                }
+                self.patch_jump(end_jump);
+            }
+
+            Stmt::While { expr, stmt } => {
+                let loop_start = self.chunk.code.len();
+                self.expr(expr);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.nested(stmt);
+                self.emit_loop(loop_start);
+
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+            }
+
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer);
+                }
+
+                let loop_start = self.chunk.code.len();
+                let exit_jump = condition.as_ref().map(|condition| {
+                    self.expr(condition);
+                    let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit(OpCode::Pop);
+                    exit_jump
+                });
+
+                self.nested(body);
+
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                    self.emit(OpCode::Pop);
+                }
+                self.emit_loop(loop_start);
+
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump);
+                    self.emit(OpCode::Pop);
+                }
+                self.end_scope();
+            }
+
+            Stmt::ForIn {
+                name,
+                keyword,
+                iterable,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                self.line = keyword.line;
+                self.emit(OpCode::IterStart);
+
+                let loop_start = self.chunk.code.len();
+                let exit_jump = self.emit_jump(OpCode::IterNext);
+
+                // The value pushed by `IterNext` becomes the loop variable.
+                self.begin_scope();
+                self.add_local(name);
+                self.stmt(body);
+                self.end_scope();
+
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+            }
+
+            Stmt::Throw { keyword, value } => {
+                self.expr(value);
+                self.line = keyword.line;
+                self.emit(OpCode::Throw);
+            }
+
+            Stmt::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => {
+                self.line = keyword.line;
+                let finally_handler = finally
+                    .as_ref()
+                    .map(|_| self.emit_jump(OpCode::PushFinally));
+                let catch_handler = catch.as_ref().map(|_| self.emit_jump(OpCode::PushCatch));
+
+                self.stmt(body);
+
+                if let (Some(catch), Some(handler)) = (catch, catch_handler) {
+                    self.emit(OpCode::PopHandler);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+
+                    // The VM pushes the caught value, which becomes the
+                    // catch variable.
+                    self.patch_jump(handler);
+                    self.begin_scope();
+                    self.add_local(&catch.name);
+                    self.stmt(&catch.body);
+                    self.end_scope();
+                    self.patch_jump(end_jump);
+                }
+
+                // `finally` is compiled twice: once for leaving normally and
+                // once for unwinding, which goes on with the error after it.
+                if let (Some(finally), Some(handler)) = (finally, finally_handler) {
+                    self.emit(OpCode::PopHandler);
+                    self.stmt(finally);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+
+                    self.patch_jump(handler);
+                    self.stmt(finally);
+                    self.emit(OpCode::Rethrow);
+                    self.patch_jump(end_jump);
+                }
+            }
+        }
+    }
+
+    /// Compiles a branch or loop body that is not necessarily a block.
+    ///
+    /// Inside a block the body gets its own scope, as in the resolver, so a
+    /// bare `var` there never leaves a slot allocated on only one path (or
+    /// once per iteration). At the top level it declares a global.
+    fn nested(&mut self, stmt: &Stmt) {
+        if self.scope_depth == 0 {
+            return self.stmt(stmt);
+        }
+
+        self.begin_scope();
+        self.stmt(stmt);
+        self.end_scope();
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal { value, .. } => match value {
+                LiteralValue::Nil => self.emit(OpCode::Nil),
+                LiteralValue::True => self.emit(OpCode::True),
+                LiteralValue::False => self.emit(OpCode::False),
+                _ => self.emit_constant(value.clone()),
+            },
+
+            Expr::Grouping { expression } => self.expr(expression),
+
+            Expr::Unary {
+                operator,
+                expression,
+            } => {
+                self.expr(expression);
+                self.line = operator.line;
+                match operator.token_type {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not),
+                }
+            }
+
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expr(left);
+                self.expr(right);
+                self.line = operator.line;
+
+                let op = match operator.token_type {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    _ => OpCode::NotEqual,
+                };
+                self.emit(op);
+            }
+
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expr(left);
+                self.line = operator.line;
+                self.emit(OpCode::CheckBool);
+
+                let end_jump = if operator.token_type == TokenType::And {
+                    self.emit_jump(OpCode::JumpIfFalse)
+                } else {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump);
+                    end_jump
+                };
+
+                self.emit(OpCode::Pop);
+                self.expr(right);
+                self.line = operator.line;
+                self.emit(OpCode::CheckBool);
+                self.patch_jump(end_jump);
+            }
+
+            Expr::Variable { name, .. } => {
+                self.line = name.line;
+                match self.resolve_local(name) {
+                    Some(slot) => self.emit_bytes(OpCode::GetLocal, &[slot]),
+                    None => self.emit_name(OpCode::GetGlobal, name),
+                }
+            }
+
+            Expr::Assign { name, value, .. } => {
+                self.expr(value);
+                self.line = name.line;
+                match self.resolve_local(name) {
+                    Some(slot) => self.emit_bytes(OpCode::SetLocal, &[slot]),
+                    None => self.emit_name(OpCode::SetGlobal, name),
+                }
+            }
+
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expr(element);
+                }
+                let count = self.u16_operand(elements.len(), "Too many list elements");
+                self.emit_bytes(OpCode::BuildList, &count.to_be_bytes());
+            }
+
+            Expr::Range {
+                start,
+                operator,
+                end,
+            } => {
+                self.expr(start);
+                self.expr(end);
+                self.line = operator.line;
+                match operator.token_type {
+                    TokenType::DotDotEqual => self.emit(OpCode::RangeInclusive),
+                    _ => self.emit(OpCode::Range),
+                }
+            }
+
+            Expr::Map { brace, entries } => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                self.line = brace.line;
+                let count = self.u16_operand(entries.len(), "Too many map entries");
+                self.emit_bytes(OpCode::BuildMap, &count.to_be_bytes());
+            }
+
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.line = bracket.line;
+                self.emit(OpCode::Index);
+            }
+
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+                self.line = bracket.line;
+                self.emit(OpCode::SetIndex);
+            }
+
+            Expr::Get { object, name } => {
+                self.expr(object);
+                self.line = name.line;
+                self.emit_name(OpCode::GetProperty, name);
+            }
+
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let target = match callee.as_ref() {
+                    Expr::Get { object, name } => {
+                        self.expr(object);
+                        Some((OpCode::Invoke, name))
+                    }
+                    Expr::Variable { name, .. }
+                        if self.resolve_local(name).is_none() && native::exists(&name.lexeme) =>
+                    {
+                        Some((OpCode::CallNative, name))
+                    }
+                    _ => {
+                        self.expr(callee);
+                        None
+                    }
+                };
+
+                for argument in arguments {
+                    self.expr(argument);
+                }
+
+                let count = match u8::try_from(arguments.len()) {
+                    Ok(count) => count,
+                    Err(_) => {
+                        self.error(paren, "Can't have more than 255 arguments");
+                        0
+                    }
+                };
+
+                match target {
+                    Some((op, name)) => {
+                        self.line = name.line;
+                        self.emit_name(op, name);
+                        self.chunk.write(count, self.line);
+                    }
+                    None => {
+                        self.line = paren.line;
+                        self.emit_bytes(OpCode::Call, &[count]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+
+        while self
+            .locals
+            .last()
+            .is_some_and(|local| local.depth > self.scope_depth)
+        {
+            self.locals.pop();
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    fn add_local(&mut self, name: &Token) {
+        if self.locals.len() > u8::MAX as usize {
+            self.error(name, "Too many local variables in scope");
+            return;
+        }
+
+        self.locals.push(Local {
+            name: name.lexeme.to_owned(),
+            depth: self.scope_depth,
+        });
+    }
+
+    fn resolve_local(&self, name: &Token) -> Option<u8> {
+        self.locals
+            .iter()
+            .rposition(|local| local.name == name.lexeme)
+            .map(|slot| slot as u8)
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        self.chunk.write(op as u8, self.line);
+    }
+
+    fn emit_bytes(&mut self, op: OpCode, operands: &[u8]) {
+        self.emit(op);
+        for &byte in operands {
+            self.chunk.write(byte, self.line);
+        }
+    }
+
+    fn emit_constant(&mut self, value: LiteralValue) {
+        let index = self.constant(value);
+        self.emit_bytes(OpCode::Constant, &index.to_be_bytes());
+    }
+
+    /// Emits `op` with the name of `token` as its constant operand.
+    fn emit_name(&mut self, op: OpCode, token: &Token) {
+        let index = self.constant(LiteralValue::StringValue(token.lexeme.to_owned()));
+        self.emit_bytes(op, &index.to_be_bytes());
+    }
+
+    fn constant(&mut self, value: LiteralValue) -> u16 {
+        let index = self.chunk.add_constant(value);
+        self.u16_operand(index, "Too many constants in one chunk")
+    }
+
+    fn u16_operand(&mut self, value: usize, msg: &str) -> u16 {
+        u16::try_from(value).unwrap_or_else(|_| {
+            ErrorMsg::report(self.line, "", msg);
+            self.had_error = true;
+            0
+        })
+    }
+
+    /// Emits a jump with a placeholder offset and returns where to patch it.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_bytes(op, &[0xff, 0xff]);
+        self.chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, operand: usize) {
+        let distance = self.chunk.code.len() - operand - 2;
+        let distance = self.u16_operand(distance, "Too much code to jump over");
+        self.chunk.code[operand..operand + 2].copy_from_slice(&distance.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        // +3 skips the `Loop` instruction itself.
+        let distance = self.chunk.code.len() - loop_start + 3;
+        let distance = self.u16_operand(distance, "Loop body too large");
+        self.emit_bytes(OpCode::Loop, &distance.to_be_bytes());
+    }
+
+    fn error(&mut self, token: &Token, msg: &str) {
+        ErrorMsg::error(token, msg);
+        self.had_error = true;
+    }
+}
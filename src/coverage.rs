@@ -0,0 +1,183 @@
+//! `--coverage`: which lines and branches of a script ran, written in the
+//! lcov tracefile format.
+//!
+//! Every line holding a statement counts as executable, so a line that
+//! never ran shows up with a count of 0. The branches are the conditions
+//! of `if`, `while` and `for`: branch 0 counts the times the condition
+//! held and branch 1 the times it did not.
+
+use crate::parser::Stmt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::Path,
+};
+
+// A statement with a condition.
+struct Branch {
+    line: usize,
+    // Times the condition was true and false.
+    taken: [u64; 2],
+}
+
+pub struct Coverage {
+    lines: BTreeMap<usize, u64>,
+    branches: Vec<Branch>,
+    // Index into `branches` by the statement's address. The statements
+    // outlive the run, so the addresses are stable.
+    index: HashMap<*const Stmt, usize>,
+}
+
+impl Coverage {
+    /// Coverage of a run of `stmts`, with nothing run yet.
+    pub fn new(stmts: &[Stmt]) -> Self {
+        let mut coverage = Self {
+            lines: BTreeMap::new(),
+            branches: Vec::new(),
+            index: HashMap::new(),
+        };
+        for stmt in stmts {
+            coverage.walk(stmt);
+        }
+        coverage
+    }
+
+    fn walk(&mut self, stmt: &Stmt) {
+        let branching = match stmt {
+            Stmt::If { .. } | Stmt::While { .. } => true,
+            Stmt::For { condition, .. } => condition.is_some(),
+            _ => false,
+        };
+        if let Some(line) = stmt.line() {
+            if !matches!(stmt, Stmt::Block(_)) {
+                self.lines.entry(line).or_default();
+            }
+            if branching {
+                self.index.insert(stmt, self.branches.len());
+                self.branches.push(Branch {
+                    line,
+                    taken: [0, 0],
+                });
+            }
+        }
+
+        match stmt {
+            Stmt::Expression(_) | Stmt::Print(_) | Stmt::Variable { .. } | Stmt::Throw { .. } => {}
+            Stmt::Block(stmts) => stmts.iter().for_each(|stmt| self.walk(stmt)),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.walk(then_branch);
+                if let Some(stmt) = else_branch {
+                    self.walk(stmt);
+                }
+            }
+            Stmt::While { stmt, .. } => self.walk(stmt),
+            Stmt::For {
+                initializer, body, ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.walk(initializer);
+                }
+                self.walk(body);
+            }
+            Stmt::ForIn { body, .. } => self.walk(body),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.walk(body);
+                if let Some(catch) = catch {
+                    self.walk(&catch.body);
+                }
+                if let Some(finally) = finally {
+                    self.walk(finally);
+                }
+            }
+        }
+    }
+
+    /// Counts a run of `stmt`.
+    pub fn hit(&mut self, stmt: &Stmt) {
+        if let Stmt::Block(_) = stmt {
+            return;
+        }
+        if let Some(line) = stmt.line() {
+            *self.lines.entry(line).or_default() += 1;
+        }
+    }
+
+    /// Counts the condition of `stmt` coming out `true` or not.
+    pub fn branch(&mut self, stmt: &Stmt, held: bool) {
+        if let Some(&index) = self.index.get(&(stmt as *const Stmt)) {
+            self.branches[index].taken[usize::from(!held)] += 1;
+        }
+    }
+
+    /// One lcov record for the script at `path`.
+    pub fn lcov(&self, path: &Path) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", path.display());
+
+        for (block, branch) in self.branches.iter().enumerate() {
+            // A condition never evaluated has no counts, written `-`.
+            let never = branch.taken == [0, 0];
+            for (number, taken) in branch.taken.iter().enumerate() {
+                let taken = if never {
+                    "-".to_string()
+                } else {
+                    taken.to_string()
+                };
+                let _ = writeln!(lcov, "BRDA:{},{block},{number},{taken}", branch.line);
+            }
+        }
+        let branches_hit = self
+            .branches
+            .iter()
+            .flat_map(|branch| branch.taken)
+            .filter(|&taken| taken > 0)
+            .count();
+        let _ = writeln!(lcov, "BRF:{}", self.branches.len() * 2);
+        let _ = writeln!(lcov, "BRH:{branches_hit}");
+
+        for (line, count) in &self.lines {
+            let _ = writeln!(lcov, "DA:{line},{count}");
+        }
+        let _ = writeln!(lcov, "LF:{}", self.lines.len());
+        let lines_hit = self.lines.values().filter(|&&count| count > 0).count();
+        let _ = writeln!(lcov, "LH:{lines_hit}");
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Coverage;
+    use crate::{
+        interpret::Interpret,
+        testing::{globals, resolve},
+    };
+    use std::path::Path;
+
+    #[test]
+    fn writes_lines_and_branches() {
+        let source = "var n = 0;\nwhile (n < 2) n = n + 1;\nif (n > 5) {\n  print n;\n}\n";
+        let stmts = resolve(source);
+
+        let mut interpret = Interpret::new().with_coverage(Coverage::new(&stmts));
+        let env = globals();
+        interpret.interpret(&stmts, env).unwrap();
+        let lcov = interpret.take_coverage().unwrap().lcov(Path::new("a.lox"));
+
+        assert_eq!(
+            lcov,
+            "TN:\nSF:a.lox\n\
+             BRDA:2,0,0,2\nBRDA:2,0,1,1\nBRDA:3,1,0,0\nBRDA:3,1,1,1\nBRF:4\nBRH:3\n\
+             DA:1,1\nDA:2,3\nDA:3,1\nDA:4,0\nLF:4\nLH:3\nend_of_record\n"
+        );
+    }
+}
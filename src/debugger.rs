@@ -0,0 +1,327 @@
+//! `rlox debug`: an interactive debugger for the tree walker.
+//!
+//! `Interpret` calls `Debugger::before` ahead of every statement. The
+//! debugger pauses there when a breakpoint or a step asks it to, then reads
+//! commands until one resumes the run.
+
+use crate::{
+    error::LoxError,
+    intern::Symbol,
+    interpret::Environment,
+    parser::{Parser, Stmt},
+    resolver::Resolver,
+    scanner::Scanner,
+    ErrorMsg,
+};
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+pub const HELP: &str = "\
+break <line>   (b)   pause whenever <line> is reached
+clear <line>         remove the breakpoint on <line>
+breakpoints          list the breakpoints
+continue       (c)   run to the next breakpoint
+step           (s)   pause at the next statement
+next           (n)   pause at the next statement, stepping over nested ones
+out            (o)   run until the enclosing block, loop or branch is done
+vars           (v)   show the locals in scope and the globals
+stack          (bt)  show the statements the run is inside
+print <expr>   (p)   evaluate <expr> here and print its value
+list           (l)   show the lines around this one
+quit           (q)   stop the run
+
+An empty line repeats the last command.";
+
+/// When to pause next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    /// At a breakpoint.
+    Continue,
+    /// At the next statement.
+    Step,
+    /// At the next statement nested no deeper than this.
+    Next(usize),
+    /// At the next statement nested less deeply than this.
+    Out(usize),
+}
+
+pub struct Debugger {
+    lines: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    resume: Resume,
+    // Line of each statement the run is inside, outermost first; `None`
+    // for blocks.
+    stack: Vec<Option<usize>>,
+    // Name of the local in each slot, as its declaration last ran.
+    locals: Vec<Option<Symbol>>,
+    input: Box<dyn FnMut() -> Option<String>>,
+    last_command: String,
+}
+
+impl Debugger {
+    /// A debugger for `source` that reads its commands from `input`, which
+    /// returns `None` once there are no more. The run starts paused.
+    pub fn new(source: &str, input: impl FnMut() -> Option<String> + 'static) -> Self {
+        Self {
+            lines: source.lines().map(str::to_string).collect(),
+            breakpoints: BTreeSet::new(),
+            resume: Resume::Step,
+            stack: Vec::new(),
+            locals: Vec::new(),
+            input: Box::new(input),
+            last_command: String::new(),
+        }
+    }
+
+    /// Records that the local in `slot` is now called `name`.
+    pub fn declare(&mut self, slot: usize, name: &Symbol) {
+        if slot >= self.locals.len() {
+            self.locals.resize(slot + 1, None);
+        }
+        self.locals[slot] = Some(name.clone());
+    }
+
+    /// Called before `stmt` runs, `depth` statements deep. Returns
+    /// `LoxError::Stopped` if the user quits.
+    pub fn before(
+        &mut self,
+        stmt: &Stmt,
+        env: &Rc<RefCell<Environment>>,
+        depth: usize,
+    ) -> Result<(), LoxError> {
+        // Blocks only group statements; they are never paused at.
+        let line = match stmt {
+            Stmt::Block(_) => None,
+            stmt => stmt.line(),
+        };
+        self.stack.truncate(depth - 1);
+        let Some(line) = line else {
+            self.stack.push(None);
+            return Ok(());
+        };
+
+        // In `for (...) print i;` the breakpoint stops at the loop only.
+        let nested = self.stack.contains(&Some(line));
+        self.stack.push(Some(line));
+
+        let pause = !nested && self.breakpoints.contains(&line)
+            || match self.resume {
+                Resume::Continue => false,
+                Resume::Step => true,
+                Resume::Next(limit) => depth <= limit,
+                Resume::Out(limit) => depth < limit,
+            };
+
+        if pause {
+            self.show(line);
+            self.pause(line, depth, env)?;
+        }
+        Ok(())
+    }
+
+    fn show(&self, line: usize) {
+        let text = self.lines.get(line - 1).map_or("", |text| text.trim());
+        println!("[line {line}] {text}");
+    }
+
+    /// Reads commands until one resumes the run.
+    fn pause(
+        &mut self,
+        line: usize,
+        depth: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        loop {
+            let Some(input) = (self.input)() else {
+                // Out of commands: run to the end.
+                self.breakpoints.clear();
+                self.resume = Resume::Continue;
+                return Ok(());
+            };
+
+            let input = match input.trim() {
+                "" => self.last_command.clone(),
+                input => input.to_string(),
+            };
+            self.last_command = input.clone();
+            let (command, argument) = input.split_once(' ').unwrap_or((&input, ""));
+            let argument = argument.trim();
+
+            match command {
+                "continue" | "c" => return self.resume(Resume::Continue),
+                "step" | "s" => return self.resume(Resume::Step),
+                "next" | "n" => return self.resume(Resume::Next(depth)),
+                "out" | "o" => return self.resume(Resume::Out(depth)),
+                "quit" | "q" => return Err(LoxError::Stopped { line }),
+
+                "break" | "b" => match self.line_number(argument) {
+                    Some(target) => {
+                        self.breakpoints.insert(target);
+                        println!("Breakpoint at line {target}.");
+                    }
+                    None => println!("No line '{argument}' to break at."),
+                },
+                "clear" => match self.line_number(argument) {
+                    Some(target) if self.breakpoints.remove(&target) => {
+                        println!("Removed the breakpoint at line {target}.")
+                    }
+                    _ => println!("No breakpoint at line '{argument}'."),
+                },
+                "breakpoints" => {
+                    if self.breakpoints.is_empty() {
+                        println!("No breakpoints.");
+                    }
+                    for target in &self.breakpoints {
+                        self.show(*target);
+                    }
+                }
+
+                "vars" | "v" => self.vars(env),
+                "stack" | "bt" => {
+                    let lines: Vec<_> = self.stack.iter().rev().flatten().collect();
+                    for (frame, line) in lines.iter().enumerate() {
+                        print!("#{frame} ");
+                        self.show(**line);
+                    }
+                    // Without functions the script is the only call frame.
+                    println!("#{} script", lines.len());
+                }
+                "print" | "p" if argument.is_empty() => println!("print needs an expression."),
+                "print" | "p" => self.print(argument, env),
+                "list" | "l" => {
+                    let first = line.saturating_sub(3).max(1);
+                    for number in first..=(line + 3).min(self.lines.len()) {
+                        let marker = if number == line { ">" } else { " " };
+                        println!("{marker}{number:4} {}", self.lines[number - 1]);
+                    }
+                }
+                "help" | "h" => println!("{HELP}"),
+                _ => println!("Unknown command '{input}'; type 'help'."),
+            }
+        }
+    }
+
+    fn resume(&mut self, resume: Resume) -> Result<(), LoxError> {
+        self.resume = resume;
+        Ok(())
+    }
+
+    fn line_number(&self, argument: &str) -> Option<usize> {
+        argument
+            .parse()
+            .ok()
+            .filter(|line| (1..=self.lines.len()).contains(line))
+    }
+
+    /// The names and slots of the locals in scope, innermost last.
+    fn live_locals(&self, env: &Environment) -> Vec<(Symbol, usize)> {
+        self.locals
+            .iter()
+            .take(env.local_count())
+            .enumerate()
+            .filter_map(|(slot, name)| Some((name.clone()?, slot)))
+            .collect()
+    }
+
+    fn vars(&self, env: &Rc<RefCell<Environment>>) {
+        let env = env.borrow();
+
+        for (name, slot) in self.live_locals(&env).into_iter().rev() {
+            println!("local  {name} = {}", env.local(slot));
+        }
+        for (name, value, constant) in env.globals() {
+            let keyword = if constant { "const" } else { "var" };
+            println!("global {keyword} {name} = {value}");
+        }
+    }
+
+    /// Evaluates `source` as if it were written at the paused statement.
+    fn print(&self, source: &str, env: &Rc<RefCell<Environment>>) {
+        let locals = self.live_locals(&env.borrow());
+        let (stmts, errors) = ErrorMsg::collect(|| {
+            let mut scanner = Scanner::new(source);
+            let stmts = Parser::new(scanner.scan_tokens().to_vec()).repl().parse();
+            Resolver::with_locals(&locals).resolve(&stmts);
+            stmts
+        });
+
+        if let Some(error) = errors.first() {
+            println!("Error: {}", error.message);
+            return;
+        }
+        let [Stmt::Expression(expr)] = stmts.as_slice() else {
+            println!("Error: Expect a single expression");
+            return;
+        };
+
+        match expr.evaluate(env.clone()) {
+            Ok(value) => println!("{value}"),
+            Err(error) => println!("RuntimeError: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Debugger;
+    use crate::{
+        error::LoxError,
+        interpret::{Environment, Interpret},
+        testing::{globals, resolve},
+        token::LiteralValue,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn debug(source: &str, commands: &[&str]) -> (Result<(), LoxError>, Rc<RefCell<Environment>>) {
+        let env = globals();
+        let stmts = resolve(source);
+
+        let mut commands = commands
+            .iter()
+            .map(|command| command.to_string())
+            .collect::<Vec<_>>()
+            .into_iter();
+        let debugger = Debugger::new(source, move || commands.next());
+        let result = Interpret::new()
+            .with_debugger(debugger)
+            .interpret(&stmts, env.clone());
+        (result, env)
+    }
+
+    #[test]
+    fn quit_stops_at_breakpoint() {
+        let source = "var n = 0;\nwhile (true) {\n  n = n + 1;\n}\n";
+        let (result, env) = debug(source, &["b 3", "c", "c", "c", "q"]);
+
+        assert_eq!(result, Err(LoxError::Stopped { line: 3 }));
+        assert_eq!(
+            env.borrow().get(&"n".into()),
+            Some(LiteralValue::IntValue(2))
+        );
+    }
+
+    #[test]
+    fn evaluates_in_paused_scope() {
+        let source = "var out = 0;\n{\n  var a = 2;\n  out = a;\n}\n";
+        let (result, env) = debug(source, &["b 4", "c", "p a = a + 3", "c"]);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            env.borrow().get(&"out".into()),
+            Some(LiteralValue::IntValue(5))
+        );
+    }
+
+    #[test]
+    fn next_steps_over_loop_body() {
+        let source = "var n = 0;\nfor (x in 0..3) {\n  n = n + x;\n}\nn = -n;\n";
+        // Stepping over the loop pauses next at line 5; quitting there
+        // leaves the loop's result untouched.
+        let (result, env) = debug(source, &["n", "n", "q"]);
+
+        assert_eq!(result, Err(LoxError::Stopped { line: 5 }));
+        assert_eq!(
+            env.borrow().get(&"n".into()),
+            Some(LiteralValue::IntValue(3))
+        );
+    }
+}
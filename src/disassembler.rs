@@ -0,0 +1,144 @@
+use crate::chunk::{Chunk, OpCode};
+use std::fmt::Write;
+
+/// Renders `chunk` one instruction per line:
+///
+/// ```text
+/// == script ==
+/// 0000    1 Constant            0 '1'
+/// 0003    | DefineGlobal        1 'a'
+/// ```
+///
+/// Columns are the byte offset, the source line (`|` when unchanged from
+/// the previous instruction), the opcode and its decoded operands.
+pub fn disassemble(chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {name} ==\n");
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = instruction(chunk, offset, &mut out);
+    }
+
+    out
+}
+
+/// Appends the instruction at `offset` to `out` and returns the offset of
+/// the next one.
+pub fn instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let _ = write!(out, "{offset:04} ");
+
+    let line = chunk.line(offset);
+    if offset > 0 && line == chunk.line(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{line:4} ");
+    }
+
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            let _ = writeln!(out, "Unknown opcode {byte}");
+            return offset + 1;
+        }
+    };
+
+    let name = format!("{op:?}");
+    let next = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::DefineConstGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty => {
+            let index = chunk.read_u16(offset + 1);
+            let _ = write!(out, "{name:<16} {index:4} ");
+            constant(chunk, index, out);
+            offset + 3
+        }
+
+        OpCode::GetLocal | OpCode::SetLocal | OpCode::Call => {
+            let _ = write!(out, "{name:<16} {:4}", chunk.code[offset + 1]);
+            offset + 2
+        }
+
+        OpCode::BuildList | OpCode::BuildMap => {
+            let _ = write!(out, "{name:<16} {:4}", chunk.read_u16(offset + 1));
+            offset + 3
+        }
+
+        OpCode::Jump
+        | OpCode::JumpIfFalse
+        | OpCode::IterNext
+        | OpCode::PushCatch
+        | OpCode::PushFinally => {
+            let target = offset + 3 + chunk.read_u16(offset + 1) as usize;
+            let _ = write!(out, "{name:<16} {offset:4} -> {target}");
+            offset + 3
+        }
+
+        OpCode::Loop => {
+            let target = offset + 3 - chunk.read_u16(offset + 1) as usize;
+            let _ = write!(out, "{name:<16} {offset:4} -> {target}");
+            offset + 3
+        }
+
+        OpCode::Invoke | OpCode::CallNative => {
+            let index = chunk.read_u16(offset + 1);
+            let count = chunk.code[offset + 3];
+            let _ = write!(out, "{name:<16} ({count} args) {index:4} ");
+            constant(chunk, index, out);
+            offset + 4
+        }
+
+        _ => {
+            out.push_str(&name);
+            offset + 1
+        }
+    };
+
+    out.push('\n');
+    next
+}
+
+fn constant(chunk: &Chunk, index: u16, out: &mut String) {
+    match chunk.constants.get(index as usize) {
+        Some(value) => {
+            let _ = write!(out, "'{value}'");
+        }
+        None => out.push_str("<missing>"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::disassemble;
+    use crate::testing::compile;
+
+    #[test]
+    fn listing() {
+        let source = "var a = 1;\nwhile (a < 3)\n  a = a + 1;";
+        let chunk = compile(source);
+
+        assert_eq!(
+            disassemble(&chunk, "script"),
+            "\
+== script ==
+0000    1 Constant            0 '1'
+0003    | DefineGlobal        1 'a'
+0006    2 GetGlobal           1 'a'
+0009    | Constant            2 '3'
+0012    | Less
+0013    | JumpIfFalse        13 -> 31
+0016    | Pop
+0017    3 GetGlobal           1 'a'
+0020    | Constant            0 '1'
+0023    | Add
+0024    | SetGlobal           1 'a'
+0027    | Pop
+0028    | Loop               28 -> 6
+0031    | Pop
+0032    | Return
+"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    intern::symbol,
+    map::{self, LoxMap},
+    token::{LiteralValue, Token},
+    token_type::TokenType,
+};
+use std::{fmt, time::Duration};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    /// Raised while executing a program, reported at `token`.
+    Runtime { token: Token, message: String },
+
+    /// A `throw` statement nothing caught.
+    Thrown { value: LiteralValue, line: usize },
+
+    /// The run executed more than `Limits::max_steps` steps.
+    StepLimit { line: usize, limit: u64 },
+
+    /// The run took longer than `Limits::timeout`.
+    Timeout { line: usize, limit: Duration },
+
+    /// Nesting went deeper than `Limits::max_depth`.
+    DepthLimit { line: usize, limit: usize },
+
+    /// Nesting went deeper than the interpreter's own stack allows,
+    /// whatever the limits.
+    StackOverflow { line: usize },
+
+    /// The strings, lists and maps alive held more than
+    /// `Limits::max_heap_bytes`.
+    HeapLimit { line: usize, limit: usize },
+
+    /// The user quit the debugger.
+    Stopped { line: usize },
+}
+
+impl LoxError {
+    pub fn runtime(token: &Token, message: impl Into<String>) -> Self {
+        LoxError::Runtime {
+            token: token.clone(),
+            message: message.into(),
+        }
+    }
+
+    /// Whether `catch` and `finally` see this error. Exceeding a limit, or
+    /// quitting the debugger, ends the run no matter what the script does
+    /// about it.
+    pub fn is_catchable(&self) -> bool {
+        matches!(self, LoxError::Runtime { .. } | LoxError::Thrown { .. })
+    }
+
+    /// The value a `catch` clause binds: what was thrown, or for a runtime
+    /// error a map with its `message` and `line`.
+    pub fn into_value(self) -> LiteralValue {
+        match self {
+            LoxError::Thrown { value, .. } => value,
+            error => {
+                let line = error.line();
+                let brace = Token::new(TokenType::LeftBrace, None, symbol!("{"), line);
+                let mut map = LoxMap::default();
+                for (key, value) in [
+                    (
+                        symbol!("message"),
+                        LiteralValue::StringValue(error.to_string().into()),
+                    ),
+                    (symbol!("line"), LiteralValue::IntValue(line as i64)),
+                ] {
+                    map.insert(LiteralValue::StringValue(key), value, &brace)
+                        .expect("string keys are hashable");
+                }
+                map::new(map)
+            }
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        match self {
+            LoxError::Runtime { token, .. } => token.line,
+            LoxError::Thrown { line, .. } => *line,
+            LoxError::StepLimit { line, .. }
+            | LoxError::Timeout { line, .. }
+            | LoxError::DepthLimit { line, .. }
+            | LoxError::StackOverflow { line }
+            | LoxError::HeapLimit { line, .. }
+            | LoxError::Stopped { line } => *line,
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Runtime { message, .. } => write!(f, "{message}"),
+            LoxError::Thrown { value, .. } => write!(f, "{value}"),
+            LoxError::StepLimit { limit, .. } => write!(f, "Step limit of {limit} exceeded."),
+            LoxError::Timeout { limit, .. } => {
+                write!(f, "Timed out after {} ms.", limit.as_millis())
+            }
+            LoxError::DepthLimit { limit, .. } => {
+                write!(f, "Nesting depth limit of {limit} exceeded.")
+            }
+            LoxError::StackOverflow { .. } => write!(f, "Stack overflow."),
+            LoxError::HeapLimit { limit, .. } => {
+                write!(f, "Heap limit of {limit} bytes exceeded.")
+            }
+            LoxError::Stopped { .. } => write!(f, "Stopped by the debugger."),
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use crate::{
+    error::LoxError,
     interpret::Environment,
+    list,
+    map::{self, LoxMap},
+    native, profiler,
+    resolver::Slot,
     token::{LiteralValue, Token},
     token_type::TokenType,
 };
 
-use std::{boxed::Box, cell::RefCell, rc::Rc};
+use std::{boxed::Box, cell::RefCell, fmt, rc::Rc};
 
 #[derive(Debug)]
 pub enum Expr {
@@ -25,10 +30,12 @@ pub enum Expr {
 
     Literal {
         value: LiteralValue,
+        line: usize,
     },
 
     Variable {
         name: Token,
+        slot: Slot,
     },
 
     Logical {
@@ -40,223 +47,495 @@ pub enum Expr {
     Assign {
         name: Token,
         value: Box<Expr>,
+        slot: Slot,
     },
-}
-
-impl Expr {
-    pub fn evaluate(&self, env: Rc<RefCell<Environment>>) -> LiteralValue {
-        match self {
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => {
-                let op = |condition: bool| {
-                    if condition {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }
-                };
-                match operator.token_type {
-                    TokenType::Minus => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
-                                LiteralValue::IntValue(a - b)
-                            }
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => {
-                                LiteralValue::FValue(a - b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
 
-                    TokenType::Slash => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
-                                LiteralValue::IntValue(a / b)
-                            }
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => {
-                                LiteralValue::FValue(a / b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    List {
+        elements: Vec<Expr>,
+    },
 
-                    TokenType::Star => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
-                                LiteralValue::IntValue(a * b)
-                            }
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => {
-                                LiteralValue::FValue(a * b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    /// `start..end` or `start..=end`, told apart by `operator`.
+    Range {
+        start: Box<Expr>,
+        operator: Token,
+        end: Box<Expr>,
+    },
 
-                    TokenType::Plus => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
-                                LiteralValue::IntValue(a + b)
-                            }
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => {
-                                LiteralValue::FValue(a + b)
-                            }
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                LiteralValue::StringValue(format!("{}{}", a, b))
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
 
-                    TokenType::Greater => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a > b),
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a > b),
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                op(a > b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    /// `object[index]`
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
 
-                    TokenType::Less => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a < b),
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a < b),
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                op(a < b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    /// `object[index] = value`
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
 
-                    TokenType::GreaterEqual => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a >= b),
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a >= b),
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                op(a >= b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    /// `object.name`; only valid as the callee of a `Call`.
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
 
-                    TokenType::LessEqual => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a <= b),
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a <= b),
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                op(a <= b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+}
 
-                    TokenType::EqualEqual => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a == b),
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a == b),
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                op(a == b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+impl Expr {
+    /// Line of the first token in the expression.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::Binary { left, operator, .. } | Expr::Logical { left, operator, .. } => {
+                left.line().or(Some(operator.line))
+            }
+            Expr::Range {
+                start, operator, ..
+            } => start.line().or(Some(operator.line)),
+            Expr::Unary { operator, .. } => Some(operator.line),
+            Expr::Grouping { expression } => expression.line(),
+            Expr::Literal { line, .. } => Some(*line),
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => Some(name.line),
+            Expr::List { elements } => elements.iter().find_map(Expr::line),
+            Expr::Map { brace, .. } => Some(brace.line),
+            Expr::Index {
+                object, bracket, ..
+            }
+            | Expr::SetIndex {
+                object, bracket, ..
+            } => object.line().or(Some(bracket.line)),
+            Expr::Get { object, name } => object.line().or(Some(name.line)),
+            Expr::Call { callee, paren, .. } => callee.line().or(Some(paren.line)),
+        }
+    }
 
-                    TokenType::BangEqual => {
-                        match (left.evaluate(env.clone()), right.evaluate(env.clone())) {
-                            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a != b),
-                            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a != b),
-                            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
-                                op(a != b)
-                            }
-                            _ => todo!(),
-                        }
-                    }
+    pub fn evaluate(&self, env: Rc<RefCell<Environment>>) -> Result<LiteralValue, LoxError> {
+        match self {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = left.evaluate(env.clone())?;
+                let right = right.evaluate(env.clone())?;
 
-                    _ => {
-                        println!("Defsault case");
-                        todo!()
-                    }
-                }
+                binary(operator, left, right)
             }
             Expr::Unary {
                 operator,
                 expression,
-            } => match (&operator.token_type, expression.evaluate(env.clone())) {
-                (TokenType::Minus, LiteralValue::IntValue(x)) => LiteralValue::IntValue(-x),
-                (TokenType::Minus, LiteralValue::FValue(x)) => LiteralValue::FValue(-x),
-                (TokenType::Bang, LiteralValue::True) => LiteralValue::False,
-                (TokenType::Bang, LiteralValue::False) => LiteralValue::True,
-                _ => {
-                    println!("Not yet handled");
-                    todo!()
-                }
+            } => unary(operator, expression.evaluate(env)?),
+            Expr::Variable { name, slot } => match slot.get() {
+                Some(slot) => Ok(env.borrow().local(slot)),
+                None => env.borrow().read(name),
             },
-            Expr::Variable { name } => env.borrow().get(&name.lexeme),
 
-            Expr::Assign { name, value } => {
-                let value = value.evaluate(env.clone());
+            Expr::Assign { name, value, slot } => {
+                let value = value.evaluate(env.clone())?;
                 let mut env = env.borrow_mut();
-                env.assign(&name.lexeme, value)
+                match slot.get() {
+                    Some(slot) => {
+                        env.set_local(slot, value.clone());
+                        Ok(value)
+                    }
+                    None => env.assign(name, value),
+                }
             }
 
             Expr::Logical {
                 left,
                 operator,
                 right,
-            } => match (
-                right.evaluate(env.clone()),
-                &operator.token_type,
-                left.evaluate(env.clone()),
-            ) {
-                (LiteralValue::True, TokenType::And, LiteralValue::True) => LiteralValue::True,
-                (LiteralValue::True, TokenType::And, LiteralValue::False) => LiteralValue::False,
-
-                (LiteralValue::False, TokenType::And, LiteralValue::True | LiteralValue::False) => {
-                    LiteralValue::False
+            } => {
+                let left = boolean(operator, left.evaluate(env.clone())?)?;
+
+                // Short-circuit: `false and _` and `true or _` skip the right side.
+                let short_circuit = match operator.token_type {
+                    TokenType::And => left == LiteralValue::False,
+                    _ => left == LiteralValue::True,
+                };
+
+                if short_circuit {
+                    return Ok(left);
                 }
 
-                (LiteralValue::True, TokenType::Or, LiteralValue::True | LiteralValue::False) => {
-                    LiteralValue::True
+                boolean(operator, right.evaluate(env)?)
+            }
+
+            Expr::List { elements } => {
+                let values = elements
+                    .iter()
+                    .map(|element| element.evaluate(env.clone()))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(list::new(values))
+            }
+
+            Expr::Range {
+                start,
+                operator,
+                end,
+            } => {
+                let start = start.evaluate(env.clone())?;
+                range(operator, start, end.evaluate(env)?)
+            }
+
+            Expr::Map { brace, entries } => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let key = key.evaluate(env.clone())?;
+                    map.insert(key, value.evaluate(env.clone())?, brace)?;
                 }
-                (LiteralValue::False, TokenType::Or, LiteralValue::True) => LiteralValue::True,
 
-                (LiteralValue::False, TokenType::Or, LiteralValue::False) => LiteralValue::False,
+                Ok(map::new(map))
+            }
 
-                _ => todo!(),
-            },
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = object.evaluate(env.clone())?;
+                get_index(bracket, object, index.evaluate(env)?)
+            }
+
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = object.evaluate(env.clone())?;
+                let index = index.evaluate(env.clone())?;
+                set_index(bracket, object, index, value.evaluate(env)?)
+            }
+
+            Expr::Get { name, .. } => Err(LoxError::runtime(
+                name,
+                format!("Method '{}' must be called.", name.lexeme),
+            )),
+
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let evaluate = |arguments: &[Expr]| {
+                    arguments
+                        .iter()
+                        .map(|argument| argument.evaluate(env.clone()))
+                        .collect::<Result<Vec<_>, _>>()
+                };
+
+                match callee.as_ref() {
+                    Expr::Get { object, name } => {
+                        let object = object.evaluate(env.clone())?;
+                        let arguments = evaluate(arguments)?;
+                        let kind = match object {
+                            LiteralValue::List(_) => "list",
+                            LiteralValue::Map(_) => "map",
+                            _ => "value",
+                        };
+                        profiler::call(
+                            || format!("{kind}.{}", name.lexeme),
+                            || invoke(name, object, arguments),
+                        )
+                    }
+                    Expr::Variable { name, slot }
+                        if slot.get().is_none() && native::exists(&name.lexeme) =>
+                    {
+                        let arguments = evaluate(arguments)?;
+                        profiler::call(|| name.lexeme.to_string(), || native::call(name, arguments))
+                    }
+                    _ => Err(LoxError::runtime(paren, "Can only call methods.")),
+                }
+            }
 
             Expr::Grouping { expression } => expression.evaluate(env),
-            Expr::Literal { value } => value.clone(),
+            Expr::Literal { value, .. } => Ok(value.clone()),
         }
     }
 }
 
-impl ToString for Expr {
-    fn to_string(&self) -> String {
+/// Applies a binary operator to two evaluated operands.
+///
+/// Shared by the tree walker and the VM so both agree on results and errors.
+pub fn binary(
+    operator: &Token,
+    left: LiteralValue,
+    right: LiteralValue,
+) -> Result<LiteralValue, LoxError> {
+    let op = |condition: bool| {
+        if condition {
+            LiteralValue::True
+        } else {
+            LiteralValue::False
+        }
+    };
+
+    let value = match operator.token_type {
+        TokenType::Minus => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
+                integer(operator, a.checked_sub(*b))?
+            }
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => LiteralValue::FValue(a - b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::Slash => match (&left, &right) {
+            (LiteralValue::IntValue(_), LiteralValue::IntValue(0)) => {
+                return Err(LoxError::runtime(operator, "Division by zero."))
+            }
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
+                integer(operator, a.checked_div(*b))?
+            }
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => LiteralValue::FValue(a / b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::Star => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
+                integer(operator, a.checked_mul(*b))?
+            }
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => LiteralValue::FValue(a * b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::Plus => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => {
+                integer(operator, a.checked_add(*b))?
+            }
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => LiteralValue::FValue(a + b),
+            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => {
+                LiteralValue::StringValue(format!("{}{}", a, b).into())
+            }
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::Greater => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a > b),
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a > b),
+            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => op(a > b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::Less => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a < b),
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a < b),
+            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => op(a < b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::GreaterEqual => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a >= b),
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a >= b),
+            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => op(a >= b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::LessEqual => match (&left, &right) {
+            (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => op(a <= b),
+            (LiteralValue::FValue(a), LiteralValue::FValue(b)) => op(a <= b),
+            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => op(a <= b),
+            _ => return Err(operands_error(operator)),
+        },
+
+        TokenType::EqualEqual => op(left == right),
+
+        TokenType::BangEqual => op(left != right),
+
+        _ => return Err(operands_error(operator)),
+    };
+
+    Ok(value)
+}
+
+pub fn unary(operator: &Token, value: LiteralValue) -> Result<LiteralValue, LoxError> {
+    match (&operator.token_type, value) {
+        (TokenType::Minus, LiteralValue::IntValue(x)) => integer(operator, x.checked_neg()),
+        (TokenType::Minus, LiteralValue::FValue(x)) => Ok(LiteralValue::FValue(-x)),
+        (TokenType::Bang, LiteralValue::True) => Ok(LiteralValue::False),
+        (TokenType::Bang, LiteralValue::False) => Ok(LiteralValue::True),
+        _ => Err(LoxError::runtime(
+            operator,
+            format!("Invalid operand for unary '{}'.", operator.lexeme),
+        )),
+    }
+}
+
+pub fn range(
+    operator: &Token,
+    start: LiteralValue,
+    end: LiteralValue,
+) -> Result<LiteralValue, LoxError> {
+    match (start, end) {
+        (LiteralValue::IntValue(start), LiteralValue::IntValue(end)) => Ok(LiteralValue::Range {
+            start,
+            end,
+            inclusive: operator.token_type == TokenType::DotDotEqual,
+        }),
+        _ => Err(LoxError::runtime(
+            operator,
+            "Range bounds must be integers.",
+        )),
+    }
+}
+
+/// `object[index]`
+pub fn get_index(
+    bracket: &Token,
+    object: LiteralValue,
+    index: LiteralValue,
+) -> Result<LiteralValue, LoxError> {
+    match &object {
+        LiteralValue::List(items) => list::get(items, &index, bracket),
+        LiteralValue::Map(map) => map::get(map, &index, bracket),
+        _ => Err(LoxError::runtime(
+            bracket,
+            "Only lists and maps can be indexed.",
+        )),
+    }
+}
+
+/// `object[index] = value`
+pub fn set_index(
+    bracket: &Token,
+    object: LiteralValue,
+    index: LiteralValue,
+    value: LiteralValue,
+) -> Result<LiteralValue, LoxError> {
+    match &object {
+        LiteralValue::List(items) => list::set(items, &index, value, bracket),
+        LiteralValue::Map(map) => map::set(map, index, value, bracket),
+        _ => Err(LoxError::runtime(
+            bracket,
+            "Only lists and maps can be indexed.",
+        )),
+    }
+}
+
+/// `object.name(arguments)`
+pub fn invoke(
+    name: &Token,
+    object: LiteralValue,
+    arguments: Vec<LiteralValue>,
+) -> Result<LiteralValue, LoxError> {
+    match &object {
+        LiteralValue::List(items) => list::call_method(items, name, arguments),
+        LiteralValue::Map(map) => map::call_method(map, name, arguments),
+        _ => Err(LoxError::runtime(
+            name,
+            format!("Undefined method '{}'.", name.lexeme),
+        )),
+    }
+}
+
+/// Operands of `and`/`or` must be booleans.
+pub fn boolean(operator: &Token, value: LiteralValue) -> Result<LiteralValue, LoxError> {
+    match value {
+        LiteralValue::True | LiteralValue::False => Ok(value),
+        _ => Err(LoxError::runtime(operator, "Operands must be booleans.")),
+    }
+}
+
+// Integer arithmetic fails rather than wrapping around.
+fn integer(operator: &Token, value: Option<i64>) -> Result<LiteralValue, LoxError> {
+    value
+        .map(LiteralValue::IntValue)
+        .ok_or_else(|| LoxError::runtime(operator, "Integer overflow."))
+}
+
+fn operands_error(operator: &Token) -> LoxError {
+    LoxError::runtime(
+        operator,
+        format!("Invalid operands for '{}'.", operator.lexeme),
+    )
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expr::Binary {
                 left,
                 operator,
                 right,
-            } => format!(
-                "({} {} {})",
-                operator.lexeme,
-                left.to_string(),
-                right.to_string()
-            ),
+            } => write!(f, "({} {} {})", operator.lexeme, left, right),
 
             Expr::Unary {
                 operator,
                 expression,
-            } => format!("({} {})", operator.lexeme, expression.to_string()),
+            } => write!(f, "({} {})", operator.lexeme, expression),
+
+            Expr::Grouping { expression } => write!(f, "(group {})", expression),
+            Expr::Literal {
+                value: LiteralValue::StringValue(s),
+                ..
+            } => write!(f, "{:?}", s),
+            Expr::Literal { value, .. } => write!(f, "{}", value),
+            Expr::Variable { name, .. } => write!(f, "{}", name.lexeme),
+
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => write!(f, "({} {} {})", operator.lexeme, left, right),
+
+            Expr::Assign { name, value, .. } => write!(f, "(= {} {})", name.lexeme, value),
+
+            Expr::List { elements } => {
+                write!(f, "(list")?;
+                for element in elements {
+                    write!(f, " {}", element)?;
+                }
+                write!(f, ")")
+            }
+
+            Expr::Range {
+                start,
+                operator,
+                end,
+            } => write!(f, "({} {} {})", operator.lexeme, start, end),
+
+            Expr::Map { entries, .. } => {
+                write!(f, "(map")?;
+                for (key, value) in entries {
+                    write!(f, " ({} {})", key, value)?;
+                }
+                write!(f, ")")
+            }
+
+            Expr::Index { object, index, .. } => write!(f, "(index {} {})", object, index),
+
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => write!(f, "(index= {} {} {})", object, index, value),
 
-            Expr::Grouping { expression } => format!("(group {})", expression.to_string()),
-            Expr::Literal { value } => format!("{}", value.to_string()),
-            _ => todo!(),
+            Expr::Get { object, name } => write!(f, "(. {} {})", object, name.lexeme),
+
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                write!(f, "(call {}", callee)?;
+                for argument in arguments {
+                    write!(f, " {}", argument)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -264,6 +543,7 @@ impl ToString for Expr {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::testing::parse;
 
     #[test]
     fn pp_ast() {
@@ -279,6 +559,7 @@ mod test {
         let group = Expr::Grouping {
             expression: Box::new(Expr::Literal {
                 value: LiteralValue::FValue(45.67),
+                line: 0,
             }),
         };
 
@@ -288,6 +569,7 @@ mod test {
                 operator: minus_token,
                 expression: Box::new(Expr::Literal {
                     value: one_two_three,
+                    line: 0,
                 }),
             }),
             operator: multi,
@@ -296,4 +578,19 @@ mod test {
 
         assert_eq!("(* (- 123) (group 45.67))", ast.to_string());
     }
+
+    #[test]
+    fn pp_statement_exprs() {
+        let source = "xs[0] = a and \"b\"; m.keys(1, [2], {3: 0..=4});";
+        let stmts = parse(source);
+
+        let exprs: Vec<_> = stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(
+            exprs,
+            [
+                "(expr (index= xs 0 (and a \"b\")))",
+                "(expr (call (. m keys) 1 (list 2) (map (3 (..= 0 4)))))",
+            ]
+        );
+    }
 }
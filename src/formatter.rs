@@ -0,0 +1,479 @@
+//! `rlox fmt`: reprints a script in the canonical style.
+//!
+//! The program is printed from its syntax tree, so spacing, indentation and
+//! line breaks all come out the same whatever the input looked like. The
+//! tree has no comments, so they are put back afterwards: formatting only
+//! ever moves tokens around, which lets every comment be matched to the
+//! token it preceded in the original and placed before or after that same
+//! token in the output.
+
+use crate::{
+    expr::Expr,
+    parser::{Parser, Stmt},
+    scanner::{Comment, Scanner},
+    token::{LiteralValue, Token},
+};
+
+/// Lists, maps and argument lists that would end past this column are
+/// split one item per line.
+const WIDTH: usize = 80;
+const INDENT: &str = "  ";
+
+/// Formats `source`, or explains why it cannot. Syntax errors are reported
+/// by the parser as usual and leave the output unusable; check for them
+/// before writing it anywhere.
+pub fn format(source: &str) -> Result<String, String> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens().to_vec();
+    let stmts = Parser::new(tokens.clone()).parse();
+
+    let mut printer = Printer {
+        out: String::new(),
+        depth: 0,
+        width: WIDTH,
+    };
+    for stmt in &stmts {
+        printer.stmt(stmt);
+        printer.out.push('\n');
+    }
+
+    restore_comments(&tokens, &scanner.comments, &printer.out)
+}
+
+struct Printer {
+    out: String,
+    depth: usize,
+    width: usize,
+}
+
+impl Printer {
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.out.push_str(&INDENT.repeat(self.depth));
+    }
+
+    fn column(&self) -> usize {
+        self.out.len() - self.out.rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expr(expr);
+                self.out.push(';');
+            }
+
+            Stmt::Print(expr) => {
+                self.out.push_str("print ");
+                self.expr(expr);
+                self.out.push(';');
+            }
+
+            Stmt::Variable { keyword, bindings } => {
+                self.out.push_str(&keyword.lexeme);
+                for (i, binding) in bindings.iter().enumerate() {
+                    self.out.push_str(if i == 0 { " " } else { ", " });
+                    self.out.push_str(&binding.token.lexeme);
+                    if let Some(initializer) = &binding.initializer {
+                        self.out.push_str(" = ");
+                        self.expr(initializer);
+                    }
+                }
+                self.out.push(';');
+            }
+
+            Stmt::Block(stmts) if stmts.is_empty() => self.out.push_str("{}"),
+            Stmt::Block(stmts) => {
+                self.out.push('{');
+                self.depth += 1;
+                for stmt in stmts {
+                    self.newline();
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+                self.newline();
+                self.out.push('}');
+            }
+
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.out.push_str("if (");
+                self.expr(condition);
+                self.out.push_str(") ");
+                self.stmt(then_branch);
+
+                if let Some(else_branch) = else_branch {
+                    if matches!(**then_branch, Stmt::Block(_)) {
+                        self.out.push(' ');
+                    } else {
+                        self.newline();
+                    }
+                    self.out.push_str("else ");
+                    self.stmt(else_branch);
+                }
+            }
+
+            Stmt::While { expr, stmt } => {
+                self.out.push_str("while (");
+                self.expr(expr);
+                self.out.push_str(") ");
+                self.stmt(stmt);
+            }
+
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                self.out.push_str("for (");
+                match initializer {
+                    Some(initializer) => self.stmt(initializer),
+                    None => self.out.push(';'),
+                }
+                if let Some(condition) = condition {
+                    self.out.push(' ');
+                    self.expr(condition);
+                }
+                self.out.push(';');
+                if let Some(increment) = increment {
+                    self.out.push(' ');
+                    self.expr(increment);
+                }
+                self.out.push_str(") ");
+                self.stmt(body);
+            }
+
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.out.push_str("for (");
+                self.out.push_str(&name.lexeme);
+                self.out.push_str(" in ");
+                self.expr(iterable);
+                self.out.push_str(") ");
+                self.stmt(body);
+            }
+
+            Stmt::Throw { value, .. } => {
+                self.out.push_str("throw ");
+                self.expr(value);
+                self.out.push(';');
+            }
+
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.out.push_str("try ");
+                self.stmt(body);
+                if let Some(catch) = catch {
+                    self.out.push_str(" catch (");
+                    self.out.push_str(&catch.name.lexeme);
+                    self.out.push_str(") ");
+                    self.stmt(&catch.body);
+                }
+                if let Some(finally) = finally {
+                    self.out.push_str(" finally ");
+                    self.stmt(finally);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal { value, .. } => self.out.push_str(&literal(value)),
+            Expr::Variable { name, .. } => self.out.push_str(&name.lexeme),
+
+            Expr::Assign { name, value, .. } => {
+                self.out.push_str(&name.lexeme);
+                self.out.push_str(" = ");
+                self.expr(value);
+            }
+
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expr(left);
+                self.out.push(' ');
+                self.out.push_str(&operator.lexeme);
+                self.out.push(' ');
+                self.expr(right);
+            }
+
+            Expr::Range {
+                start,
+                operator,
+                end,
+            } => {
+                self.expr(start);
+                self.out.push_str(&operator.lexeme);
+                self.expr(end);
+            }
+
+            Expr::Unary {
+                operator,
+                expression,
+            } => {
+                self.out.push_str(&operator.lexeme);
+                self.expr(expression);
+            }
+
+            Expr::Grouping { expression } => {
+                self.out.push('(');
+                self.expr(expression);
+                self.out.push(')');
+            }
+
+            Expr::List { elements } => self.items("[", elements, "]", Self::expr),
+
+            Expr::Map { entries, .. } => self.items("{", entries, "}", |printer, (key, value)| {
+                printer.expr(key);
+                printer.out.push_str(": ");
+                printer.expr(value);
+            }),
+
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            }
+
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(object);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push_str("] = ");
+                self.expr(value);
+            }
+
+            Expr::Get { object, name } => {
+                self.expr(object);
+                self.out.push('.');
+                self.out.push_str(&name.lexeme);
+            }
+
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                self.items("(", arguments, ")", Self::expr);
+            }
+        }
+    }
+
+    /// Prints `items` between `open` and `close` on the current line if
+    /// they fit, or else one per line.
+    fn items<T>(&mut self, open: &str, items: &[T], close: &str, item: fn(&mut Self, &T)) {
+        let mut flat = Printer {
+            out: String::new(),
+            depth: self.depth,
+            width: usize::MAX,
+        };
+        flat.out.push_str(open);
+        for (i, value) in items.iter().enumerate() {
+            if i > 0 {
+                flat.out.push_str(", ");
+            }
+            item(&mut flat, value);
+        }
+        flat.out.push_str(close);
+
+        if items.is_empty() || self.column() + flat.out.len() <= self.width {
+            self.out.push_str(&flat.out);
+            return;
+        }
+
+        self.out.push_str(open);
+        self.depth += 1;
+        for (i, value) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.newline();
+            item(self, value);
+        }
+        self.depth -= 1;
+        self.newline();
+        self.out.push_str(close);
+    }
+}
+
+fn literal(value: &LiteralValue) -> String {
+    match value {
+        LiteralValue::StringValue(s) => format!("\"{s}\""),
+        // Keep the point, or the number would read back as an integer.
+        LiteralValue::FValue(f) if f.fract() == 0.0 => format!("{f}.0"),
+        value => value.to_string(),
+    }
+}
+
+/// What goes before a line of the formatted output.
+enum Before {
+    Blank,
+    Comment(String),
+}
+
+/// Puts the comments of the original source back into `formatted`, and
+/// keeps single blank lines where the original separated lines with any.
+fn restore_comments(
+    original: &[Token],
+    comments: &[Comment],
+    formatted: &str,
+) -> Result<String, String> {
+    let mut scanner = Scanner::new(formatted);
+    let printed = scanner.scan_tokens();
+
+    // Formatting must not change the program, only the space between
+    // tokens; anything else is a bug here.
+    if let Some((old, _)) = original
+        .iter()
+        .zip(printed)
+        .find(|(old, new)| old.token_type != new.token_type || old.literal != new.literal)
+    {
+        return Err(format!(
+            "Formatting would change the code on line {}",
+            old.line
+        ));
+    }
+    if original.len() != printed.len() {
+        return Err("Formatting would change the code".to_string());
+    }
+
+    let lines: Vec<&str> = formatted.lines().collect();
+    let mut before: Vec<Vec<Before>> = (0..=lines.len()).map(|_| Vec::new()).collect();
+    let mut after: Vec<Vec<&str>> = vec![Vec::new(); lines.len()];
+
+    let mut comments = comments.iter().peekable();
+    let mut last_line = None;
+    for (i, (old, new)) in original.iter().zip(printed).enumerate() {
+        // Output line the token landed on; the end of file goes after all.
+        let line = if i + 1 == printed.len() {
+            lines.len()
+        } else {
+            new.line - 1
+        };
+
+        while let Some(comment) = comments.next_if(|comment| comment.next_token == i) {
+            let blank = last_line.is_some_and(|last| comment.line > last + 1);
+            last_line = Some(comment.line);
+
+            match i.checked_sub(1) {
+                Some(previous) if !comment.own_line => {
+                    after[printed[previous].line - 1].push(&comment.text)
+                }
+                _ => {
+                    if blank {
+                        before[line].push(Before::Blank);
+                    }
+                    before[line].push(Before::Comment(comment.text.clone()));
+                }
+            }
+        }
+
+        let first_on_line = i == 0 || printed[i - 1].line != new.line;
+        if first_on_line && last_line.is_some_and(|last| old.line > last + 1) {
+            before[line].push(Before::Blank);
+        }
+        last_line = Some(old.line);
+    }
+
+    let mut out = String::new();
+    for (i, before) in before.iter().enumerate() {
+        let line = lines.get(i).copied().unwrap_or("");
+        let mut indent = line[..line.len() - line.trim_start().len()].to_string();
+        // Comments at the end of a block belong inside it.
+        if line.trim_start().starts_with('}') {
+            indent.push_str(INDENT);
+        }
+
+        for entry in before {
+            match entry {
+                // One blank line at most, and none at the start of a block
+                // or the file or before a closing brace.
+                Before::Blank => {
+                    if !(out.is_empty()
+                        || out.ends_with("\n\n")
+                        || out.ends_with("{\n")
+                        || line.trim_start().starts_with('}'))
+                    {
+                        out.push('\n');
+                    }
+                }
+                Before::Comment(text) => {
+                    out.push_str(&indent);
+                    out.push_str(text);
+                    out.push('\n');
+                }
+            }
+        }
+
+        if i < lines.len() {
+            out.push_str(line);
+            for comment in &after[i] {
+                out.push(' ');
+                out.push_str(comment);
+            }
+            out.push('\n');
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::format;
+
+    #[test]
+    fn reprints_in_canonical_style() {
+        let source = "var  a=1,b ;\nif(a>0){print a;}else print -b;\nfor(var i=0;i<3;i=i+1)print i;\nfor(;;){}\n";
+
+        assert_eq!(
+            format(source).unwrap(),
+            "var a = 1, b;\nif (a > 0) {\n  print a;\n} else print -b;\nfor (var i = 0; i < 3; i = i + 1) print i;\nfor (;;) {}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let source = "// header\n\nvar a = [1,\n  2]; // trailing\n\n\n{\n  // inside\n  print a;\n// last\n}\n// end\n";
+
+        assert_eq!(
+            format(source).unwrap(),
+            "// header\n\nvar a = [1, 2]; // trailing\n\n{\n  // inside\n  print a;\n  // last\n}\n// end\n"
+        );
+    }
+
+    #[test]
+    fn splits_long_lists() {
+        let source = format!("var xs = [{}];", ["\"element\""; 10].join(", "));
+        let formatted = format(&source).unwrap();
+
+        assert!(formatted.starts_with("var xs = [\n  \"element\",\n"));
+        assert!(formatted.ends_with("  \"element\"\n];\n"));
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+}
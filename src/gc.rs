@@ -0,0 +1,328 @@
+//! Cycle collector for lists and maps.
+//!
+//! Values are reference counted, which frees everything except cycles such
+//! as `xs.push(xs)`. Every list and map registers here when it is created,
+//! and a collection clears the ones that nothing outside the heap can reach,
+//! which breaks their cycles and lets the counts drop to zero.
+//!
+//! Roots are found from the counts rather than by walking the interpreter:
+//! a reference held by the VM stack, the globals, a local slot or a value
+//! mid-evaluation is a strong count no tracked object accounts for, so the
+//! object holding it, and everything it reaches, survives.
+
+use crate::{
+    intern,
+    list::List,
+    map::{LoxMap, Map},
+    token::LiteralValue,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem,
+    rc::{Rc, Weak},
+};
+
+/// Collect automatically once this many objects are tracked, or twice the
+/// survivors of the last collection if that is more.
+const MIN_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub collections: usize,
+    pub freed_objects: usize,
+    /// Estimated from the containers' capacities and string contents.
+    pub freed_bytes: usize,
+    pub live_objects: usize,
+}
+
+enum Tracked {
+    List(Weak<RefCell<Vec<LiteralValue>>>),
+    Map(Weak<RefCell<LoxMap>>),
+}
+
+enum Object {
+    List(List),
+    Map(Map),
+}
+
+struct Heap {
+    objects: Vec<Tracked>,
+    // Estimated bytes the live objects hold, not counting strings: measured
+    // by the last collection, plus what was tracked or grown since.
+    bytes: usize,
+    next_gc: usize,
+    stress: bool,
+    stats: Stats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            bytes: 0,
+            next_gc: MIN_THRESHOLD,
+            stress: false,
+            stats: Stats::default(),
+        }
+    }
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+pub fn track_list(list: &List) {
+    track(
+        Tracked::List(Rc::downgrade(list)),
+        Object::List(list.clone()).size(),
+    );
+}
+
+pub fn track_map(map: &Map) {
+    track(
+        Tracked::Map(Rc::downgrade(map)),
+        Object::Map(map.clone()).size(),
+    );
+}
+
+/// Counts a tracked list or map growing by `values` slots.
+pub fn grow(values: usize) {
+    HEAP.with_borrow_mut(|heap| heap.bytes += values * mem::size_of::<LiteralValue>());
+}
+
+/// Runs a collection now and returns the estimated bytes freed. Interned
+/// strings the freed objects held are released too.
+pub fn collect() -> usize {
+    let freed = HEAP.with_borrow_mut(Heap::collect);
+    intern::sweep();
+    freed
+}
+
+/// Collect on every allocation, to shake out objects freed too early.
+pub fn set_stress(stress: bool) {
+    HEAP.with_borrow_mut(|heap| heap.stress = stress);
+}
+
+/// Estimated bytes held by lists, maps and strings. It only overestimates:
+/// what was freed since the last collection still counts.
+pub fn bytes() -> usize {
+    HEAP.with_borrow(|heap| heap.bytes) + intern::bytes()
+}
+
+pub fn stats() -> Stats {
+    HEAP.with_borrow(|heap| heap.stats)
+}
+
+fn track(object: Tracked, bytes: usize) {
+    HEAP.with_borrow_mut(|heap| {
+        heap.objects.push(object);
+        heap.bytes += bytes;
+        if heap.stress || heap.objects.len() >= heap.next_gc {
+            heap.collect();
+        }
+    });
+}
+
+impl Heap {
+    fn collect(&mut self) -> usize {
+        let objects: Vec<Object> = self.objects.iter().filter_map(Tracked::upgrade).collect();
+        let index: HashMap<usize, usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (object.address(), i))
+            .collect();
+
+        // References each object receives from other tracked objects.
+        let mut internal = vec![0; objects.len()];
+        for object in &objects {
+            for child in object.children() {
+                if let Some(&i) = index.get(&child) {
+                    internal[i] += 1;
+                }
+            }
+        }
+
+        // `objects` itself holds one strong reference to each.
+        let mut reachable: Vec<bool> = objects
+            .iter()
+            .zip(&internal)
+            .map(|(object, &internal)| object.strong_count() - 1 > internal)
+            .collect();
+        let mut pending: Vec<usize> = (0..objects.len()).filter(|&i| reachable[i]).collect();
+
+        while let Some(i) = pending.pop() {
+            for child in objects[i].children() {
+                if let Some(&j) = index.get(&child) {
+                    if !reachable[j] {
+                        reachable[j] = true;
+                        pending.push(j);
+                    }
+                }
+            }
+        }
+
+        let mut freed_bytes = 0;
+        let mut freed_objects = 0;
+        let mut garbage = Vec::new();
+        self.bytes = 0;
+        for (object, &live) in objects.iter().zip(&reachable) {
+            if live {
+                self.bytes += object.size();
+            } else {
+                freed_bytes += object.size() + object.strings();
+                freed_objects += 1;
+                garbage.push(object.clear());
+            }
+        }
+
+        // Dropping the contents releases the references that formed the
+        // cycles; dropping `objects` then releases the last ones.
+        drop(garbage);
+        drop(objects);
+
+        self.objects.retain(Tracked::is_alive);
+        self.next_gc = MIN_THRESHOLD.max(self.objects.len() * 2);
+
+        self.stats.collections += 1;
+        self.stats.freed_objects += freed_objects;
+        self.stats.freed_bytes += freed_bytes;
+        self.stats.live_objects = self.objects.len();
+
+        freed_bytes
+    }
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::List(list) => list.upgrade().map(Object::List),
+            Tracked::Map(map) => map.upgrade().map(Object::Map),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::List(list) => list.strong_count() > 0,
+            Tracked::Map(map) => map.strong_count() > 0,
+        }
+    }
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::List(list) => Rc::as_ptr(list) as *const () as usize,
+            Object::Map(map) => Rc::as_ptr(map) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::List(list) => Rc::strong_count(list),
+            Object::Map(map) => Rc::strong_count(map),
+        }
+    }
+
+    /// Addresses of the lists and maps directly inside this object. An
+    /// object borrowed elsewhere reports none, which only keeps more alive.
+    fn children(&self) -> Vec<usize> {
+        let mut children = Vec::new();
+        let mut visit = |value: &LiteralValue| match value {
+            LiteralValue::List(list) => children.push(Rc::as_ptr(list) as *const () as usize),
+            LiteralValue::Map(map) => children.push(Rc::as_ptr(map) as *const () as usize),
+            _ => {}
+        };
+
+        match self {
+            Object::List(list) => {
+                if let Ok(list) = list.try_borrow() {
+                    list.iter().for_each(&mut visit);
+                }
+            }
+            Object::Map(map) => {
+                if let Ok(map) = map.try_borrow() {
+                    map.entries().iter().for_each(|(_, value)| visit(value));
+                }
+            }
+        }
+
+        children
+    }
+
+    /// Estimated bytes of the object itself, from its capacity. An object
+    /// borrowed elsewhere counts only its header.
+    fn size(&self) -> usize {
+        match self {
+            Object::List(list) => {
+                mem::size_of::<RefCell<Vec<LiteralValue>>>()
+                    + list
+                        .try_borrow()
+                        .map_or(0, |list| list.capacity() * mem::size_of::<LiteralValue>())
+            }
+            Object::Map(map) => {
+                mem::size_of::<RefCell<LoxMap>>()
+                    + map.try_borrow().map_or(0, |map| {
+                        map.entries().len() * 2 * mem::size_of::<LiteralValue>()
+                    })
+            }
+        }
+    }
+
+    /// Bytes of the strings directly inside the object.
+    fn strings(&self) -> usize {
+        let strings = |value: &LiteralValue| match value {
+            LiteralValue::StringValue(s) => s.len(),
+            _ => 0,
+        };
+
+        match self {
+            Object::List(list) => list.borrow().iter().map(strings).sum(),
+            Object::Map(map) => map
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(key, value)| strings(key) + strings(value))
+                .sum(),
+        }
+    }
+
+    /// Empties the object and hands back its former contents.
+    fn clear(&self) -> Vec<LiteralValue> {
+        match self {
+            Object::List(list) => mem::take(&mut *list.borrow_mut()),
+            Object::Map(map) => mem::take(&mut *map.borrow_mut())
+                .into_entries()
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collect, stats};
+    use crate::{list, map, token::LiteralValue};
+    use std::rc::Rc;
+
+    #[test]
+    fn frees_cycles_only() {
+        let before = stats();
+
+        let cycle = list::new(vec![]);
+        let LiteralValue::List(items) = &cycle else {
+            unreachable!()
+        };
+        items.borrow_mut().push(cycle.clone());
+        let weak = Rc::downgrade(items);
+        drop(cycle);
+
+        let kept = list::new(vec![list::new(vec![]), map::new(Default::default())]);
+
+        assert!(collect() > 0);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(stats().freed_objects - before.freed_objects, 1);
+        assert_eq!(kept.to_string(), "[[], {}]");
+    }
+}
@@ -0,0 +1,185 @@
+//! Interned strings for identifiers and string values.
+//!
+//! Every distinct string has exactly one allocation, so cloning a `Symbol`
+//! is a reference count bump and comparing or hashing two symbols looks
+//! only at the pointer.
+
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+/// Sweep unused strings once the table has grown past this many entries,
+/// or twice the survivors of the last sweep if that is more.
+const MIN_SWEEP: usize = 4096;
+
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+struct Interner {
+    strings: HashSet<Entry>,
+    // Total length of `strings`.
+    bytes: usize,
+    next_sweep: usize,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        strings: HashSet::new(),
+        bytes: 0,
+        next_sweep: MIN_SWEEP,
+    });
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Self {
+        INTERNER.with_borrow_mut(|interner| {
+            if let Some(Entry(existing)) = interner.strings.get(s) {
+                return Symbol(existing.clone());
+            }
+
+            if interner.strings.len() >= interner.next_sweep {
+                interner.sweep();
+            }
+
+            let string: Rc<str> = Rc::from(s);
+            interner.strings.insert(Entry(string.clone()));
+            interner.bytes += s.len();
+            Symbol(string)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Interns a string literal once per thread and call site, for lexemes
+/// rebuilt on hot paths.
+macro_rules! symbol {
+    ($s:literal) => {{
+        thread_local!(static SYMBOL: $crate::intern::Symbol = $crate::intern::Symbol::intern($s));
+        SYMBOL.with($crate::intern::Symbol::clone)
+    }};
+}
+pub(crate) use symbol;
+
+/// Drops strings no symbol refers to any more.
+pub fn sweep() {
+    INTERNER.with_borrow_mut(Interner::sweep);
+}
+
+/// Bytes of string contents in the table, including strings unused since
+/// the last sweep.
+pub fn bytes() -> usize {
+    INTERNER.with_borrow(|interner| interner.bytes)
+}
+
+impl Interner {
+    fn sweep(&mut self) {
+        self.strings
+            .retain(|Entry(string)| Rc::strong_count(string) > 1);
+        self.bytes = self.strings.iter().map(|Entry(string)| string.len()).sum();
+        self.next_sweep = MIN_SWEEP.max(self.strings.len() * 2);
+    }
+}
+
+// The table is looked up by contents, unlike `Symbol` itself.
+#[derive(PartialEq, Eq, Hash)]
+struct Entry(Rc<str>);
+
+impl Borrow<str> for Entry {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(Rc::as_ptr(&self.0) as *const u8, state);
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(s: String) -> Self {
+        Symbol::intern(&s)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sweep, Symbol, INTERNER};
+
+    #[test]
+    fn equal_strings_share_one_allocation() {
+        let a = Symbol::intern("name");
+        let b = Symbol::from(String::from("na") + "me");
+
+        assert_eq!(a, b);
+        assert!(std::ptr::eq(a.as_str(), b.as_str()));
+        assert_ne!(a, Symbol::intern("other"));
+    }
+
+    #[test]
+    fn sweep_keeps_live_strings() {
+        let kept = Symbol::intern("kept");
+        drop(Symbol::intern("dropped"));
+        sweep();
+
+        let count = INTERNER.with_borrow(|interner| interner.strings.len());
+        assert_eq!(count, 1);
+        assert_eq!(kept, Symbol::intern("kept"));
+    }
+}
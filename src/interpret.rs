@@ -1,120 +1,520 @@
-use crate::{parser::Stmt, token::LiteralValue, token_type::TokenType};
-use std::{cell::RefCell, collections::HashMap, ops::RangeInclusive, rc::Rc};
+use crate::{
+    coverage::Coverage,
+    debugger::Debugger,
+    error::LoxError,
+    expr::Expr,
+    intern::Symbol,
+    iter::LoxIter,
+    limits::{Budget, Limits},
+    parser::Stmt,
+    profiler::Profiler,
+    token::{LiteralValue, Token},
+    token_type::TokenType,
+    trace::Frame,
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
+/// Runtime variable storage.
+///
+/// Globals are looked up by name. Block-scoped variables live in `locals`
+/// at the slot the resolver assigned them, so reaching one is an index and
+/// entering a block allocates nothing.
 #[derive(Default, Debug, Clone)]
 pub struct Environment {
-    define: HashMap<String, LiteralValue>,
-    enclosing: Option<Box<Rc<RefCell<Environment>>>>,
+    define: HashMap<Symbol, LiteralValue>,
+    // Names bound by `const`/`let`, mapped to the token that declared them.
+    constants: HashMap<Symbol, Token>,
+    locals: Vec<LiteralValue>,
 }
 
 impl Environment {
-    pub fn new(enclosing: Option<Box<Rc<RefCell<Environment>>>>) -> Self {
-        Self {
-            enclosing,
-            ..Default::default()
-        }
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn define(&mut self, key: String, value: LiteralValue) {
+    pub fn define(&mut self, key: Symbol, value: LiteralValue) {
+        self.constants.remove(&key);
         self.define.insert(key, value);
     }
 
-    pub fn assign(&mut self, key: &str, value: LiteralValue) -> LiteralValue {
-        if self.define.contains_key(key) {
-            let inserted = self.define.insert(key.to_string(), value);
-            inserted.unwrap()
-        } else {
-            if let Some(enclosing) = self.enclosing.as_ref() {
-                return enclosing.borrow_mut().assign(key, value);
-            };
+    pub fn define_constant(&mut self, token: &Token, value: LiteralValue) {
+        self.define.insert(token.lexeme.to_owned(), value);
+        self.constants
+            .insert(token.lexeme.to_owned(), token.to_owned());
+    }
+
+    pub fn assign(&mut self, name: &Token, value: LiteralValue) -> Result<LiteralValue, LoxError> {
+        let key = &name.lexeme;
 
-            println!("Var: {} not found in this scope", key);
-            LiteralValue::Nil
+        if let Some(declaration) = self.constants.get(key) {
+            return Err(LoxError::runtime(
+                name,
+                format!(
+                    "Cannot assign to constant '{}' declared on line {}.",
+                    key, declaration.line
+                ),
+            ));
+        }
+
+        match self.define.get_mut(key) {
+            Some(slot) => {
+                *slot = value.clone();
+                Ok(value)
+            }
+            None => Err(undefined(name)),
         }
     }
 
-    pub fn get(&self, key: &str) -> LiteralValue {
-        let value = self
+    /// The global `name`, which must have been defined.
+    pub fn read(&self, name: &Token) -> Result<LiteralValue, LoxError> {
+        self.get(&name.lexeme).ok_or_else(|| undefined(name))
+    }
+
+    pub fn get(&self, key: &Symbol) -> Option<LiteralValue> {
+        self.define.get(key).cloned()
+    }
+
+    /// Every global with its value, sorted by name, and whether it is
+    /// constant.
+    pub fn globals(&self) -> Vec<(Symbol, LiteralValue, bool)> {
+        let mut globals: Vec<_> = self
             .define
-            .get(key)
-            .unwrap_or(&LiteralValue::Nil)
-            .to_owned();
-
-        match value {
-            LiteralValue::Nil => {
-                // if eclosing is None
-                let Some(enclosing) = self.enclosing.as_ref() else {
-                    return LiteralValue::Nil;
-                };
-
-                return enclosing.borrow().get(key);
-            }
-            _ => value,
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.clone(),
+                    value.clone(),
+                    self.constants.contains_key(name),
+                )
+            })
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// How many local slots are in use.
+    pub fn local_count(&self) -> usize {
+        self.locals.len()
+    }
+
+    /// A declaration skipped by control flow leaves its slot unset, which
+    /// reads as `nil`.
+    pub fn local(&self, slot: usize) -> LiteralValue {
+        self.locals.get(slot).cloned().unwrap_or(LiteralValue::Nil)
+    }
+
+    pub fn set_local(&mut self, slot: usize, value: LiteralValue) {
+        if slot >= self.locals.len() {
+            self.locals.resize(slot + 1, LiteralValue::Nil);
         }
+        self.locals[slot] = value;
     }
 }
 
-pub struct Interpret;
+fn undefined(name: &Token) -> LoxError {
+    LoxError::runtime(name, format!("Undefined variable '{}'.", name.lexeme))
+}
+
+#[derive(Default)]
+pub struct Interpret {
+    budget: Budget,
+    // Calls in progress, outermost first. Left as they were when a run
+    // fails, for the error's stack trace.
+    frames: Vec<Frame>,
+    debugger: Option<Debugger>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+}
 
 impl Interpret {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
     }
 
-    pub fn interpret(&mut self, stmts: &[Stmt], env: Rc<RefCell<Environment>>) {
-        for stmt in stmts {
-            self.execute(stmt, env.clone());
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.budget = Budget::new(limits);
+        self
+    }
+
+    /// Lets `debugger` pause the run before any statement.
+    pub fn with_debugger(mut self, debugger: Debugger) -> Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Lets `profiler` time every statement.
+    pub fn with_profiler(mut self, profiler: Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// The profiler given to `with_profiler`, with what it measured.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Lets `coverage` count the statements and branches that run.
+    pub fn with_coverage(mut self, coverage: Coverage) -> Self {
+        self.coverage = Some(coverage);
+        self
+    }
+
+    /// The coverage given to `with_coverage`, with what it counted.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    pub fn interpret(
+        &mut self,
+        stmts: &[Stmt],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        self.budget.start();
+        self.frames = vec![Frame::script()];
+
+        stmts
+            .iter()
+            .try_for_each(|stmt| self.execute(stmt, env.clone()))
+    }
+
+    /// The calls in progress when the last run failed, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Runs `f` in a block scope, dropping the locals it declared afterwards,
+    /// even when it fails.
+    fn scoped(
+        &mut self,
+        env: &Rc<RefCell<Environment>>,
+        f: impl FnOnce(&mut Self) -> Result<(), LoxError>,
+    ) -> Result<(), LoxError> {
+        let depth = env.borrow().locals.len();
+        let result = f(self);
+        env.borrow_mut().locals.truncate(depth);
+        result
+    }
+
+    fn execute(&mut self, stmt: &Stmt, env: Rc<RefCell<Environment>>) -> Result<(), LoxError> {
+        self.budget.step(stmt.line())?;
+        self.budget.enter()?;
+        let paused = match &mut self.debugger {
+            Some(debugger) => debugger.before(stmt, &env, self.budget.depth()),
+            None => Ok(()),
+        };
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(stmt);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(stmt);
+        }
+        let result = paused.and_then(|()| self.run(stmt, env));
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+        self.budget.exit();
+
+        result
+    }
+
+    // Evaluates the condition of `stmt`, counting the outcome for coverage.
+    fn condition(
+        &mut self,
+        stmt: &Stmt,
+        condition: &Expr,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<bool, LoxError> {
+        let held = condition.evaluate(env.clone())? == LiteralValue::True;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.branch(stmt, held);
+        }
+        Ok(held)
+    }
+
+    // Tells the debugger, if any, which local a slot now holds.
+    fn declared(&mut self, slot: usize, name: &Token) {
+        if let Some(debugger) = &mut self.debugger {
+            debugger.declare(slot, &name.lexeme);
         }
     }
 
-    fn execute(&mut self, stmt: &Stmt, env: Rc<RefCell<Environment>>) {
+    fn run(&mut self, stmt: &Stmt, env: Rc<RefCell<Environment>>) -> Result<(), LoxError> {
         match stmt {
             Stmt::Expression(expr) => {
-                expr.evaluate(env);
+                expr.evaluate(env)?;
             }
 
             Stmt::Print(expr) => {
-                println!("{}", expr.evaluate(env.clone()).to_string());
+                println!("{}", expr.evaluate(env.clone())?);
             }
 
-            Stmt::Variable { token, expression } => {
-                let value = expression.evaluate(env.clone());
-                let name = token.lexeme.to_owned();
+            Stmt::Variable { keyword, bindings } => {
+                for binding in bindings {
+                    let value = match &binding.initializer {
+                        Some(expression) => expression.evaluate(env.clone())?,
+                        None => LiteralValue::Nil,
+                    };
 
-                let mut t = env.borrow_mut();
-
-                t.define(name, value);
-            }
-
-            Stmt::Block(stmts) => {
-                let env = Rc::new(RefCell::new(Environment::new(Some(Box::new(env.clone())))));
+                    if let Some(slot) = binding.slot.get() {
+                        self.declared(slot, &binding.token);
+                    }
 
-                for stmt in stmts {
-                    self.execute(stmt, env.clone());
+                    let mut env = env.borrow_mut();
+                    if let Some(slot) = binding.slot.get() {
+                        env.set_local(slot, value);
+                    } else if keyword.token_type == TokenType::Var {
+                        env.define(binding.token.lexeme.to_owned(), value);
+                    } else {
+                        env.define_constant(&binding.token, value);
+                    }
                 }
             }
 
+            Stmt::Block(stmts) => self.scoped(&env, |interpret| {
+                stmts
+                    .iter()
+                    .try_for_each(|stmt| interpret.execute(stmt, env.clone()))
+            })?,
+
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
             } => {
-                if condition.evaluate(env.clone()) == LiteralValue::True {
-                    self.execute(then_branch, env)
-                } else {
-                    if let Some(stmt) = else_branch {
-                        self.execute(stmt, env.clone())
+                if self.condition(stmt, condition, &env)? {
+                    self.execute(then_branch, env)?;
+                } else if let Some(stmt) = else_branch {
+                    self.execute(stmt, env.clone())?;
+                }
+            }
+
+            Stmt::While { expr, stmt: body } => {
+                while self.condition(stmt, expr, &env)? {
+                    self.execute(body, env.clone())?;
+                }
+            }
+
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => self.scoped(&env, |interpret| {
+                if let Some(initializer) = initializer {
+                    interpret.execute(initializer, env.clone())?;
+                }
+
+                loop {
+                    if let Some(condition) = condition {
+                        if !interpret.condition(stmt, condition, &env)? {
+                            break;
+                        }
+                    }
+
+                    interpret.execute(body, env.clone())?;
+
+                    if let Some(increment) = increment {
+                        increment.evaluate(env.clone())?;
                     }
                 }
+
+                Ok(())
+            })?,
+
+            Stmt::ForIn {
+                name,
+                keyword,
+                iterable,
+                body,
+                slot,
+            } => {
+                let iterable = iterable.evaluate(env.clone())?;
+                let slot = slot.get().expect("for-in variable is always local");
+
+                self.scoped(&env, |interpret| {
+                    interpret.declared(slot, name);
+                    for value in LoxIter::new(iterable, keyword)? {
+                        env.borrow_mut().set_local(slot, value);
+                        interpret.execute(body, env.clone())?;
+                    }
+                    Ok(())
+                })?;
             }
 
-            Stmt::While { expr, stmt } => {
-                while expr.evaluate(env.clone()) == LiteralValue::True {
-                    self.execute(&stmt, env.clone());
+            Stmt::Throw { keyword, value } => {
+                return Err(LoxError::Thrown {
+                    value: value.evaluate(env)?,
+                    line: keyword.line,
+                });
+            }
+
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let mut result = self.execute(body, env.clone());
+
+                if let Some(catch) = catch {
+                    result = match result {
+                        Err(error) if error.is_catchable() => {
+                            let slot = catch.slot.get().expect("catch variable is always local");
+                            self.scoped(&env, |interpret| {
+                                interpret.declared(slot, &catch.name);
+                                env.borrow_mut().set_local(slot, error.into_value());
+                                interpret.execute(&catch.body, env.clone())
+                            })
+                        }
+                        result => result,
+                    };
                 }
+
+                // Nothing runs after a limit error, not even `finally`.
+                if let Some(finally) = finally {
+                    if result.as_ref().err().is_none_or(LoxError::is_catchable) {
+                        self.execute(finally, env)?;
+                    }
+                }
+
+                return result;
             }
+        }
 
-            _ => todo!(),
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Environment, Interpret};
+    use crate::{
+        error::LoxError,
+        limits::Limits,
+        testing::{globals, resolve},
+        token::LiteralValue,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn run(source: &str) -> Rc<RefCell<Environment>> {
+        let env = globals();
+        let stmts = resolve(source);
+        Interpret::new().interpret(&stmts, env.clone()).unwrap();
+        env
+    }
+
+    #[test]
+    fn for_loop_runs_directly() {
+        let env = run("var total = 0; for (var i = 0; i < 4; i = i + 1) total = total + i;");
+
+        assert_eq!(
+            env.borrow().get(&"total".into()),
+            Some(LiteralValue::IntValue(6))
+        );
+        assert_eq!(env.borrow().get(&"i".into()), None);
+    }
+
+    #[test]
+    fn var_defaults_to_nil() {
+        let env = run("var a = 1, b = 2; { var a, c = a; b = c; }");
+
+        assert_eq!(
+            env.borrow().get(&"a".into()),
+            Some(LiteralValue::IntValue(1))
+        );
+        assert_eq!(env.borrow().get(&"b".into()), Some(LiteralValue::Nil));
+    }
+
+    #[test]
+    fn locals_shadow_and_reuse_slots() {
+        let env = run("var out = [];
+            { var a = 1; { var a = a + 10; out.push(a); } out.push(a); }
+            { var b = 2; for (x in 0..2) { var y = x * b; out.push(y); } out.push(b); }");
+
+        assert_eq!(
+            env.borrow().get(&"out".into()).unwrap().to_string(),
+            "[11, 1, 0, 2, 2]"
+        );
+        assert!(env.borrow().locals.is_empty());
+    }
+
+    #[test]
+    fn reusable_after_limit() {
+        let env = globals();
+        let mut interpret = Interpret::new().with_limits(Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        });
+        let mut run = |source: &str| {
+            let stmts = resolve(source);
+            interpret.interpret(&stmts, env.clone())
+        };
+
+        let error = run("{ var i = 0; while (true) { i = i + 1; } }").unwrap_err();
+        assert!(matches!(error, LoxError::StepLimit { limit: 100, .. }));
+        assert!(env.borrow().locals.is_empty());
+
+        run("var done = true;").unwrap();
+        assert_eq!(env.borrow().get(&"done".into()), Some(LiteralValue::True));
+    }
+
+    #[test]
+    fn limits_cannot_be_caught() {
+        let env = globals();
+        let stmts = resolve(
+            "var handled = false;
+            try { while (true) {} } catch (e) { handled = true; } finally { handled = true; }",
+        );
+
+        let error = Interpret::new()
+            .with_limits(Limits {
+                max_steps: Some(50),
+                ..Limits::default()
+            })
+            .interpret(&stmts, env.clone())
+            .unwrap_err();
+
+        assert!(matches!(error, LoxError::StepLimit { .. }));
+        assert_eq!(
+            env.borrow().get(&"handled".into()),
+            Some(LiteralValue::False)
+        );
+    }
+
+    #[test]
+    fn heap_limit_counts_bytes() {
+        for source in [
+            "var s = \"a\"; for (i in 0..64) s = s + s;",
+            "var xs = []; while (true) xs.push(xs.len());",
+        ] {
+            let stmts = resolve(source);
+
+            let error = Interpret::new()
+                .with_limits(Limits {
+                    max_heap_bytes: Some(1 << 20),
+                    ..Limits::default()
+                })
+                .interpret(&stmts, globals())
+                .unwrap_err();
+
+            assert!(matches!(error, LoxError::HeapLimit { .. }), "{source}");
         }
     }
+
+    #[test]
+    fn constant_rejected_at_runtime() {
+        let env = run("const limit = 10;");
+
+        let stmts = resolve("limit = 11;");
+        let error = Interpret::new().interpret(&stmts, env.clone()).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Cannot assign to constant 'limit' declared on line 1."
+        );
+        assert_eq!(
+            env.borrow().get(&"limit".into()),
+            Some(LiteralValue::IntValue(10))
+        );
+    }
 }
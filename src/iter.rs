@@ -0,0 +1,130 @@
+use crate::{
+    error::LoxError,
+    list::List,
+    token::{LiteralValue, Token},
+};
+
+/// Iteration protocol behind `for (x in iterable)`.
+///
+/// Lists are walked live, so elements pushed during the loop are visited.
+/// Maps yield a snapshot of their keys, strings their characters, and
+/// ranges count up lazily without materialising a list.
+pub enum LoxIter {
+    List {
+        list: List,
+        next: usize,
+    },
+    Values(std::vec::IntoIter<LiteralValue>),
+    Range {
+        next: i64,
+        end: i64,
+        inclusive: bool,
+        // Set once `next` would step past `i64::MAX`.
+        done: bool,
+    },
+}
+
+impl LoxIter {
+    pub fn new(iterable: LiteralValue, token: &Token) -> Result<LoxIter, LoxError> {
+        match &iterable {
+            LiteralValue::List(list) => Ok(LoxIter::List {
+                list: list.clone(),
+                next: 0,
+            }),
+            LiteralValue::Map(map) => Ok(LoxIter::Values(map.borrow().keys().into_iter())),
+            LiteralValue::StringValue(s) => Ok(LoxIter::Values(
+                s.chars()
+                    .map(|c| LiteralValue::StringValue(c.to_string().into()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            )),
+            LiteralValue::Range {
+                start,
+                end,
+                inclusive,
+            } => Ok(LoxIter::Range {
+                next: *start,
+                end: *end,
+                inclusive: *inclusive,
+                done: false,
+            }),
+            _ => Err(LoxError::runtime(
+                token,
+                format!("Value '{}' is not iterable.", iterable),
+            )),
+        }
+    }
+}
+
+impl Iterator for LoxIter {
+    type Item = LiteralValue;
+
+    fn next(&mut self) -> Option<LiteralValue> {
+        match self {
+            LoxIter::List { list, next } => {
+                let value = list.borrow().get(*next).cloned();
+                *next += 1;
+                value
+            }
+            LoxIter::Values(values) => values.next(),
+            LoxIter::Range {
+                next,
+                end,
+                inclusive,
+                done,
+            } => {
+                if *done || *next > *end || *next == *end && !*inclusive {
+                    return None;
+                }
+                let value = *next;
+                match next.checked_add(1) {
+                    Some(following) => *next = following,
+                    None => *done = true,
+                }
+                Some(LiteralValue::IntValue(value))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::eval;
+
+    #[test]
+    fn iterates_builtins() {
+        assert_eq!(
+            eval("var result = []; for (x in [1, 2]) result.push(x * 10);"),
+            "[10, 20]"
+        );
+        assert_eq!(
+            eval("var result = []; for (k in {\"a\": 1, \"b\": 2}) result.push(k);"),
+            "[\"a\", \"b\"]"
+        );
+        assert_eq!(
+            eval("var result = []; for (c in \"hi\") result.push(c);"),
+            "[\"h\", \"i\"]"
+        );
+        assert_eq!(
+            eval("var result = 1; for (x in 1) {}"),
+            "Value '1' is not iterable."
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            eval("var result = []; for (i in 0..3) result.push(i);"),
+            "[0, 1, 2]"
+        );
+        assert_eq!(
+            eval("var n = 2; var result = []; for (i in 1..=n + 1) result.push(i);"),
+            "[1, 2, 3]"
+        );
+        assert_eq!(eval("var result = 0..=10;"), "0..=10");
+        assert_eq!(
+            eval("var result = []; for (i in 9223372036854775806..=9223372036854775807) result.push(i);"),
+            "[9223372036854775806, 9223372036854775807]"
+        );
+    }
+}
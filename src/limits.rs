@@ -0,0 +1,186 @@
+use crate::{error::LoxError, gc};
+use std::time::{Duration, Instant};
+
+/// Resource caps for running untrusted scripts. `None` means unlimited,
+/// which is the default for every field.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Statements executed by the tree walker, or instructions by the VM.
+    pub max_steps: Option<u64>,
+    /// Wall-clock time for one call to `interpret`.
+    pub timeout: Option<Duration>,
+    /// Nesting depth of statements. The tree walker only: the VM runs
+    /// nested statements in one frame, so it never goes past 1.
+    pub max_depth: Option<usize>,
+    /// Estimated bytes held by the strings, lists and maps alive at once.
+    pub max_heap_bytes: Option<usize>,
+}
+
+/// Deepest nesting of statements and expressions the parser accepts. A
+/// chain like `1 + 2 + 3` nests one level per operator, since every pass
+/// after parsing recurses down its left side.
+pub const MAX_NESTING: usize = 4096;
+
+/// Native stack for running a program: room for every pass to recurse
+/// `MAX_NESTING` deep, with a few kilobytes a level in a debug build. Only
+/// the pages actually used are ever committed.
+pub const STACK_SIZE: usize = 256 << 20;
+
+/// Deepest nesting any run reaches before failing with "Stack overflow.",
+/// even without a `max_depth`. The tree walker recurses on the native
+/// stack, which is far smaller than the heap. No less than `MAX_NESTING`,
+/// since the VM runs whatever parses and both backends must agree.
+pub const MAX_CALL_DEPTH: usize = MAX_NESTING;
+
+// Reading the clock on every step would dominate tight loops.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Usage counted against `Limits` during one run.
+///
+/// `start` resets the counters, so a backend that failed with a limit error
+/// can run the next program under the same limits.
+#[derive(Debug)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    started: Instant,
+    line: usize,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            steps: 0,
+            depth: 0,
+            started: Instant::now(),
+            line: 0,
+        }
+    }
+
+    pub fn start(&mut self) {
+        *self = Self::new(self.limits);
+    }
+
+    /// Counts one unit of work at `line`, when known.
+    pub fn step(&mut self, line: Option<usize>) -> Result<(), LoxError> {
+        if let Some(line) = line {
+            self.line = line;
+        }
+        self.steps += 1;
+
+        if let Some(limit) = self.limits.max_steps {
+            if self.steps > limit {
+                return Err(LoxError::StepLimit {
+                    line: self.line,
+                    limit,
+                });
+            }
+        }
+
+        if let Some(limit) = self.limits.timeout {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && self.started.elapsed() > limit {
+                return Err(LoxError::Timeout {
+                    line: self.line,
+                    limit,
+                });
+            }
+        }
+
+        if let Some(limit) = self.limits.max_heap_bytes {
+            if gc::bytes() > limit {
+                // Only a collection tells dead cycles from live objects,
+                // and measures what the live ones hold.
+                gc::collect();
+                if gc::bytes() > limit {
+                    return Err(LoxError::HeapLimit {
+                        line: self.line,
+                        limit,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Enters one level of nesting; pair with `exit`.
+    pub fn enter(&mut self) -> Result<(), LoxError> {
+        self.depth += 1;
+
+        let error = match self.limits.max_depth {
+            Some(limit) if self.depth > limit => LoxError::DepthLimit {
+                line: self.line,
+                limit,
+            },
+            _ if self.depth > MAX_CALL_DEPTH => LoxError::StackOverflow { line: self.line },
+            _ => return Ok(()),
+        };
+
+        self.depth -= 1;
+        Err(error)
+    }
+
+    pub fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Levels entered and not yet exited.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Budget, Limits, MAX_CALL_DEPTH};
+    use crate::error::LoxError;
+
+    #[test]
+    fn step_limit_resets_on_start() {
+        let mut budget = Budget::new(Limits {
+            max_steps: Some(2),
+            ..Limits::default()
+        });
+
+        assert!(budget.step(Some(3)).is_ok());
+        assert!(budget.step(None).is_ok());
+        assert_eq!(
+            budget.step(None),
+            Err(LoxError::StepLimit { line: 3, limit: 2 })
+        );
+
+        budget.start();
+        assert!(budget.step(None).is_ok());
+    }
+
+    #[test]
+    fn depth_limit() {
+        let mut budget = Budget::new(Limits {
+            max_depth: Some(1),
+            ..Limits::default()
+        });
+
+        assert!(budget.enter().is_ok());
+        assert!(matches!(budget.enter(), Err(LoxError::DepthLimit { .. })));
+        budget.exit();
+        assert!(budget.enter().is_ok());
+    }
+
+    #[test]
+    fn stack_overflow_without_limits() {
+        let mut budget = Budget::default();
+
+        for _ in 0..MAX_CALL_DEPTH {
+            assert!(budget.enter().is_ok());
+        }
+        assert_eq!(budget.enter(), Err(LoxError::StackOverflow { line: 0 }));
+    }
+}
@@ -0,0 +1,618 @@
+//! `rlox lint`: warnings about code that runs but is probably a mistake.
+//!
+//! Comments switch rules off and on:
+//!
+//! ```text
+//! // lint: disable empty-block      from here to the end of the file
+//! // lint: enable empty-block       ...or until here
+//! // lint: allow unused-variable    only on this line, or the next one
+//! ```
+//!
+//! Leaving out the rule names applies the directive to every rule.
+
+use crate::{
+    expr::Expr,
+    parser::{Parser, Stmt},
+    resolver::{Record, Resolver},
+    scanner::{Comment, Scanner},
+    token::Token,
+    token_type::TokenType,
+};
+use serde_json::json;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    /// A local declared but never read.
+    UnusedVariable,
+    /// A local declared with a name already visible.
+    ShadowedVariable,
+    /// A statement after one that always throws.
+    UnreachableCode,
+    /// `if (a = b)` and the like.
+    AssignInCondition,
+    /// `a == a` and the like.
+    SelfComparison,
+    /// `{}` as a statement or body.
+    EmptyBlock,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 6] = [
+        Rule::UnusedVariable,
+        Rule::ShadowedVariable,
+        Rule::UnreachableCode,
+        Rule::AssignInCondition,
+        Rule::SelfComparison,
+        Rule::EmptyBlock,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::AssignInCondition => "assign-in-condition",
+            Rule::SelfComparison => "self-comparison",
+            Rule::EmptyBlock => "empty-block",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub rule: Rule,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Warning {
+    /// One JSON object, for `--json`.
+    pub fn to_json(&self, path: &str) -> String {
+        json!({
+            "file": path,
+            "line": self.line,
+            "rule": self.rule.name(),
+            "message": self.message,
+        })
+        .to_string()
+    }
+}
+
+/// Lints `source`, returning its warnings in line order, or an error for a
+/// malformed `lint:` comment. Syntax and resolution errors are reported as
+/// usual through `ErrorMsg`; the caller checks for them.
+pub fn lint(source: &str) -> Result<Vec<Warning>, String> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens().to_vec();
+    let stmts = Parser::new(tokens.clone()).parse();
+    let (resolved, record) = Resolver::new().record(&stmts);
+    if !resolved {
+        return Ok(Vec::new());
+    }
+
+    let mut linter = Linter::default();
+    linter.variables(&record);
+    linter.stmts(&stmts);
+    for line in empty_blocks(&tokens, &scanner.comments) {
+        linter.warn(Rule::EmptyBlock, line, "Empty block.".to_string());
+    }
+
+    let directives = Directives::new(&scanner.comments, &tokens)?;
+    let mut warnings: Vec<_> = linter
+        .warnings
+        .into_iter()
+        .filter(|warning| directives.enabled(warning))
+        .collect();
+    warnings.sort_by_key(|warning| (warning.line, warning.rule));
+
+    Ok(warnings)
+}
+
+/// Collects warnings from the tree and from what `Resolver` recorded.
+#[derive(Default)]
+struct Linter {
+    warnings: Vec<Warning>,
+}
+
+impl Linter {
+    fn warn(&mut self, rule: Rule, line: usize, message: String) {
+        self.warnings.push(Warning {
+            rule,
+            line,
+            message,
+        });
+    }
+
+    /// Locals never read, and locals declared with a name already visible.
+    /// Redefining a global is how scripts and the prompt update them, so
+    /// globals are left alone.
+    fn variables(&mut self, record: &Record) {
+        let mut read = vec![false; record.declarations.len()];
+        for used in record.uses.iter().filter(|used| !used.assigns) {
+            read[used.declaration] = true;
+        }
+
+        for (declared, read) in record.declarations.iter().zip(read) {
+            if declared.is_global() {
+                continue;
+            }
+
+            let name = &declared.name;
+            if let Some(shadowed) = declared.shadows {
+                let message = format!(
+                    "'{}' shadows the variable declared on line {}.",
+                    name.lexeme, record.declarations[shadowed].name.line
+                );
+                self.warn(Rule::ShadowedVariable, name.line, message);
+            }
+
+            // A leading underscore marks a name as deliberately unused.
+            if !read && !name.lexeme.starts_with('_') {
+                self.warn(
+                    Rule::UnusedVariable,
+                    name.line,
+                    format!("Variable '{}' is never read.", name.lexeme),
+                );
+            }
+        }
+    }
+
+    /// A statement list, as in a block or at the top level.
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        // Only the first unreachable statement is reported.
+        let mut thrown: Option<&Stmt> = None;
+
+        for stmt in stmts {
+            if let Some(throw) = thrown.take() {
+                self.warn(
+                    Rule::UnreachableCode,
+                    stmt.line().or(throw.line()).unwrap_or_default(),
+                    "Unreachable code; the statement before always throws.".to_string(),
+                );
+            } else if always_throws(stmt) {
+                thrown = Some(stmt);
+            }
+
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expr(expr),
+
+            Stmt::Variable { bindings, .. } => {
+                for initializer in bindings.iter().filter_map(|b| b.initializer.as_ref()) {
+                    self.expr(initializer);
+                }
+            }
+
+            Stmt::Block(stmts) => self.stmts(stmts),
+
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition(condition);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+
+            Stmt::While { expr, stmt } => {
+                self.condition(expr);
+                self.stmt(stmt);
+            }
+
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.condition(condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+                self.stmt(body);
+            }
+
+            Stmt::ForIn { iterable, body, .. } => {
+                self.expr(iterable);
+                self.stmt(body);
+            }
+
+            Stmt::Throw { value, .. } => self.expr(value),
+
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.stmt(body);
+                if let Some(catch) = catch {
+                    self.stmt(&catch.body);
+                }
+                if let Some(finally) = finally {
+                    self.stmt(finally);
+                }
+            }
+        }
+    }
+
+    /// The condition of an `if` or a loop.
+    fn condition(&mut self, condition: &Expr) {
+        // Parentheses around the assignment mark it as intended.
+        if let Expr::Assign { name, .. } = condition {
+            self.warn(
+                Rule::AssignInCondition,
+                name.line,
+                format!(
+                    "Assignment to '{}' used as a condition; did you mean '=='?",
+                    name.lexeme
+                ),
+            );
+        }
+
+        self.expr(condition);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                if is_comparison(&operator.token_type) && same_value(left, right) {
+                    self.warn(
+                        Rule::SelfComparison,
+                        operator.line,
+                        format!("Comparing '{}' with itself.", left),
+                    );
+                }
+                self.expr(left);
+                self.expr(right);
+            }
+
+            Expr::Logical { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+
+            Expr::Unary { expression, .. } | Expr::Grouping { expression } => self.expr(expression),
+
+            Expr::Assign { value, .. } => self.expr(value),
+
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+
+            Expr::Range { start, end, .. } => {
+                self.expr(start);
+                self.expr(end);
+            }
+
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.expr(index);
+            }
+
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+
+            Expr::Get { object, .. } => self.expr(object),
+
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+            }
+
+            Expr::Variable { .. } | Expr::Literal { .. } => {}
+        }
+    }
+}
+
+/// Lines of the `{}` blocks holding neither statements nor comments.
+///
+/// Found in the tokens because blocks keep no line in the tree. A `{` that
+/// opens a statement or follows a statement's header is a block; anywhere
+/// else `{}` is an empty map.
+fn empty_blocks(tokens: &[Token], comments: &[Comment]) -> Vec<usize> {
+    let mut lines = Vec::new();
+
+    for (index, pair) in tokens.windows(2).enumerate() {
+        let [open, close] = pair else { unreachable!() };
+        if open.token_type != TokenType::LeftBrace || close.token_type != TokenType::RightBrace {
+            continue;
+        }
+
+        let block = index == 0
+            || matches!(
+                tokens[index - 1].token_type,
+                TokenType::RightParen
+                    | TokenType::Else
+                    | TokenType::Try
+                    | TokenType::Finally
+                    | TokenType::Semicolon
+                    | TokenType::LeftBrace
+                    | TokenType::RightBrace
+            );
+        let commented = comments.iter().any(|c| c.next_token == index + 1);
+
+        if block && !commented {
+            lines.push(open.line);
+        }
+    }
+
+    lines
+}
+
+/// Whether control never falls through `stmt`.
+fn always_throws(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Throw { .. } => true,
+        Stmt::Block(stmts) => stmts.iter().any(always_throws),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_throws(then_branch) && always_throws(else_branch),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            finally.as_deref().is_some_and(always_throws)
+                || always_throws(body) && catch.as_ref().is_none_or(|c| always_throws(&c.body))
+        }
+        _ => false,
+    }
+}
+
+fn is_comparison(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::EqualEqual
+            | TokenType::BangEqual
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+    )
+}
+
+/// Whether `left` and `right` read the same variable, or the same element
+/// or property of one, and so cannot differ.
+fn same_value(left: &Expr, right: &Expr) -> bool {
+    fn place(expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable { .. } => true,
+            Expr::Grouping { expression } => place(expression),
+            Expr::Get { object, .. } => place(object),
+            Expr::Index { object, index, .. } => {
+                place(object) && (place(index) || matches!(**index, Expr::Literal { .. }))
+            }
+            _ => false,
+        }
+    }
+
+    place(left) && place(right) && left.to_string() == right.to_string()
+}
+
+/// The `lint:` comments of a file.
+struct Directives {
+    // Each rule's `disable`/`enable` switches, in line order.
+    switches: Vec<(usize, Rule, bool)>,
+    allowed: Vec<(usize, Rule)>,
+}
+
+impl Directives {
+    fn new(comments: &[Comment], tokens: &[Token]) -> Result<Self, String> {
+        let mut directives = Directives {
+            switches: Vec::new(),
+            allowed: Vec::new(),
+        };
+
+        for comment in comments {
+            let Some(directive) = comment.text[2..].trim().strip_prefix("lint:") else {
+                continue;
+            };
+            let (action, names) = directive
+                .trim()
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive.trim(), ""));
+
+            let rules = if names.trim().is_empty() {
+                Rule::ALL.to_vec()
+            } else {
+                names
+                    .split(',')
+                    .map(|name| {
+                        Rule::from_name(name.trim()).ok_or_else(|| {
+                            format!(
+                                "[line {}] Unknown lint rule '{}'",
+                                comment.line,
+                                name.trim()
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()?
+            };
+
+            match action {
+                "disable" | "enable" => directives.switches.extend(
+                    rules
+                        .into_iter()
+                        .map(|rule| (comment.line, rule, action == "enable")),
+                ),
+                "allow" => {
+                    let line = if comment.own_line {
+                        tokens
+                            .get(comment.next_token)
+                            .map_or(comment.line, |t| t.line)
+                    } else {
+                        comment.line
+                    };
+                    directives
+                        .allowed
+                        .extend(rules.into_iter().map(|rule| (line, rule)));
+                }
+                _ => {
+                    return Err(format!(
+                        "[line {}] Unknown lint directive '{}'; expected disable, enable or allow",
+                        comment.line, action
+                    ))
+                }
+            }
+        }
+
+        Ok(directives)
+    }
+
+    fn enabled(&self, warning: &Warning) -> bool {
+        let switched = self
+            .switches
+            .iter()
+            .rev()
+            .find(|(line, rule, _)| *line <= warning.line && *rule == warning.rule);
+
+        switched.is_none_or(|(_, _, enabled)| *enabled)
+            && !self.allowed.contains(&(warning.line, warning.rule))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lint, Rule};
+
+    fn rules(source: &str) -> Vec<(usize, Rule)> {
+        lint(source)
+            .unwrap()
+            .into_iter()
+            .map(|warning| (warning.line, warning.rule))
+            .collect()
+    }
+
+    #[test]
+    fn finds_each_rule() {
+        let source = "var a = 1;
+{
+  var a = 2;
+  var b = 3;
+  print a;
+}
+if (a = 2) print a == a;
+while (a < 3) {}
+if (a) {
+  throw \"stop\";
+  print 1;
+}
+try {} catch (_error) { print a[0] != a[0]; }";
+
+        assert_eq!(
+            rules(source),
+            [
+                (3, Rule::ShadowedVariable),
+                (4, Rule::UnusedVariable),
+                (7, Rule::AssignInCondition),
+                (7, Rule::SelfComparison),
+                (8, Rule::EmptyBlock),
+                (11, Rule::UnreachableCode),
+                (13, Rule::SelfComparison),
+                (13, Rule::EmptyBlock),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_intended_code_alone() {
+        let source = "var a = 1; var a = 2;
+{ var b = 1; b = b + 1; }
+var m = {};
+while (m == {}) {
+  // nothing yet
+}
+if ((a = 3)) print a == a + 1;
+for (x in [1]) print x;
+try { throw 1; } catch (e) { print e; }
+print \"reached\";";
+
+        assert_eq!(rules(source), []);
+    }
+
+    #[test]
+    fn reports_the_unreachable_line() {
+        let source = "{
+  var unused = 1;
+  throw unused;
+}
+print 2;";
+
+        assert_eq!(rules(source), [(5, Rule::UnreachableCode)]);
+    }
+
+    #[test]
+    fn comments_switch_rules() {
+        let source = "{}
+// lint: disable empty-block
+{}
+// lint: enable
+{}
+{ var a; } // lint: allow unused-variable
+// lint: allow
+{ var b; }
+{ var c; }";
+
+        assert_eq!(
+            rules(source),
+            [
+                (1, Rule::EmptyBlock),
+                (5, Rule::EmptyBlock),
+                (9, Rule::UnusedVariable)
+            ]
+        );
+        assert!(lint("// lint: disable no-such-rule\n").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{
+    error::LoxError,
+    gc,
+    token::{LiteralValue, Token},
+};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+/// Lists are shared by reference: assigning one to another variable aliases it.
+pub type List = Rc<RefCell<Vec<LiteralValue>>>;
+
+pub fn new(values: Vec<LiteralValue>) -> LiteralValue {
+    let list = Rc::new(RefCell::new(values));
+    gc::track_list(&list);
+    LiteralValue::List(list)
+}
+
+/// `list[index]`
+pub fn get(list: &List, index: &LiteralValue, bracket: &Token) -> Result<LiteralValue, LoxError> {
+    let list = list.borrow();
+    let position = position(list.len(), index, bracket)?;
+
+    Ok(list[position].clone())
+}
+
+/// `list[index] = value`
+pub fn set(
+    list: &List,
+    index: &LiteralValue,
+    value: LiteralValue,
+    bracket: &Token,
+) -> Result<LiteralValue, LoxError> {
+    let mut list = list.borrow_mut();
+    let position = position(list.len(), index, bracket)?;
+    list[position] = value.clone();
+
+    Ok(value)
+}
+
+pub fn call_method(
+    list: &List,
+    name: &Token,
+    arguments: Vec<LiteralValue>,
+) -> Result<LiteralValue, LoxError> {
+    match name.lexeme.as_str() {
+        "push" => {
+            let [value] = arity(name, arguments)?;
+            list.borrow_mut().push(value);
+            gc::grow(1);
+            Ok(LiteralValue::Nil)
+        }
+
+        "pop" => {
+            let [] = arity(name, arguments)?;
+            list.borrow_mut()
+                .pop()
+                .ok_or_else(|| LoxError::runtime(name, "Cannot pop from an empty list."))
+        }
+
+        "len" => {
+            let [] = arity(name, arguments)?;
+            Ok(LiteralValue::IntValue(list.borrow().len() as i64))
+        }
+
+        "insert" => {
+            let [index, value] = arity(name, arguments)?;
+            let mut list = list.borrow_mut();
+            // Inserting at `len` appends, so non-negative indices are checked
+            // against `len + 1`; negative ones still count back from the end.
+            let position = if integer(&index, name)? < 0 {
+                position(list.len(), &index, name)?
+            } else {
+                position(list.len() + 1, &index, name)?
+            };
+            list.insert(position, value);
+            gc::grow(1);
+            Ok(LiteralValue::Nil)
+        }
+
+        "remove" => {
+            let [index] = arity(name, arguments)?;
+            let mut list = list.borrow_mut();
+            let position = position(list.len(), &index, name)?;
+            Ok(list.remove(position))
+        }
+
+        "slice" => {
+            let list = list.borrow();
+            let (start, end) = match arguments.as_slice() {
+                [start] => (clamp(list.len(), start, name)?, list.len()),
+                [start, end] => (
+                    clamp(list.len(), start, name)?,
+                    clamp(list.len(), end, name)?,
+                ),
+                _ => {
+                    return Err(LoxError::runtime(
+                        name,
+                        format!("Expected 1 or 2 arguments but got {}.", arguments.len()),
+                    ))
+                }
+            };
+
+            Ok(new(list[start..end.max(start)].to_vec()))
+        }
+
+        "sort" => {
+            let [] = arity(name, arguments)?;
+            let mut list = list.borrow_mut();
+
+            // Checked up front so the list is left alone on an error, and
+            // so `compare` is a total order over what is sorted.
+            if !list.iter().all(|value| compare(&list[0], value).is_some()) {
+                return Err(LoxError::runtime(
+                    name,
+                    "Can only sort lists of numbers or of strings.",
+                ));
+            }
+
+            list.sort_by(|a, b| compare(a, b).unwrap_or(Ordering::Equal));
+            Ok(LiteralValue::Nil)
+        }
+
+        _ => Err(LoxError::runtime(
+            name,
+            format!("Undefined method '{}' on list.", name.lexeme),
+        )),
+    }
+}
+
+/// Checks a call received exactly `N` arguments and hands them back as an array.
+pub fn arity<const N: usize>(
+    name: &Token,
+    arguments: Vec<LiteralValue>,
+) -> Result<[LiteralValue; N], LoxError> {
+    let count = arguments.len();
+
+    arguments.try_into().map_err(|_| {
+        LoxError::runtime(name, format!("Expected {} arguments but got {}.", N, count))
+    })
+}
+
+fn integer(index: &LiteralValue, token: &Token) -> Result<i64, LoxError> {
+    match index {
+        LiteralValue::IntValue(i) => Ok(*i),
+        _ => Err(LoxError::runtime(token, "List index must be an integer.")),
+    }
+}
+
+/// Resolves a possibly negative index against `len`, failing when out of range.
+fn position(len: usize, index: &LiteralValue, token: &Token) -> Result<usize, LoxError> {
+    let i = integer(index, token)?;
+    let resolved = if i < 0 { i + len as i64 } else { i };
+
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(LoxError::runtime(
+            token,
+            format!("Index {} out of range for list of length {}.", i, len),
+        ));
+    }
+
+    Ok(resolved as usize)
+}
+
+/// Like `position`, but clamps to `0..=len` the way slice bounds do.
+fn clamp(len: usize, index: &LiteralValue, token: &Token) -> Result<usize, LoxError> {
+    let i = integer(index, token)?;
+    let resolved = if i < 0 { i + len as i64 } else { i };
+
+    Ok(resolved.clamp(0, len as i64) as usize)
+}
+
+/// Orders two ints, two strings or two floats; `None` for anything else,
+/// including NaN.
+fn compare(a: &LiteralValue, b: &LiteralValue) -> Option<Ordering> {
+    match (a, b) {
+        (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => Some(a.cmp(b)),
+        (LiteralValue::FValue(a), LiteralValue::FValue(b)) if !a.is_nan() && !b.is_nan() => {
+            Some(a.total_cmp(b))
+        }
+        (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::{eval, eval_vm};
+
+    #[test]
+    fn deep_nesting_stays_off_the_stack() {
+        let deep = "var xs = []; var ys = [];
+            for (i in 0..30000) { xs = [xs]; ys = [ys]; }";
+
+        assert_eq!(eval(&format!("{deep} var result = xs == ys;")), "true");
+        assert_eq!(
+            eval(&format!("{deep} var result = xs;")),
+            format!("{}{}", "[".repeat(30_001), "]".repeat(30_001))
+        );
+        assert_eq!(
+            eval(&format!("{deep} xs = nil; var result = ys[0][0];")).len(),
+            2 * 29_999
+        );
+    }
+
+    #[test]
+    fn index_and_assign() {
+        assert_eq!(eval("var xs = [1, 2, 3]; var result = xs[-1];"), "3");
+        assert_eq!(
+            eval("var xs = [1, 2, 3]; var ys = xs; ys[0] = 9; var result = xs;"),
+            "[9, 2, 3]"
+        );
+        assert_eq!(
+            eval("var result = [1, 2, 3][3];"),
+            "Index 3 out of range for list of length 3."
+        );
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(
+            eval("var result = [3, 1]; result.push(2); result.sort();"),
+            "[1, 2, 3]"
+        );
+        assert_eq!(
+            eval("var result = [1, 2]; result.insert(0, 0); result.insert(-1, 5);"),
+            "[0, 1, 5, 2]"
+        );
+        assert_eq!(eval("var result = [1, 2, 3, 4].slice(1, -1);"), "[2, 3]");
+        assert_eq!(
+            eval("var xs = [4, 5]; var result = xs.pop() + xs.len();"),
+            "6"
+        );
+        assert_eq!(
+            eval("var result = [].pop();"),
+            "Cannot pop from an empty list."
+        );
+    }
+
+    #[test]
+    fn sort_rejects_mixed_lists() {
+        // 40 ints and strings in an order that makes `sort_by` panic when
+        // the comparator is not a total order.
+        let source = "var xs = [];
+            for (i in 0..40) { if (i / 5 * 5 == i) xs.push(\"a\"); else xs.push(40 - i); }
+            var result = nil;
+            try { xs.sort(); } catch (e) { result = [e[\"message\"], xs[0], xs[1]]; }";
+        let expected = "[\"Can only sort lists of numbers or of strings.\", \"a\", 39]";
+
+        assert_eq!(eval(source), expected);
+        assert_eq!(eval_vm(source), expected);
+        assert_eq!(
+            eval("var result = [2.5, 0.0 / 0.0]; result.sort();"),
+            "Can only sort lists of numbers or of strings."
+        );
+    }
+}
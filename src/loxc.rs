@@ -0,0 +1,513 @@
+//! Binary `.loxc` format for compiled chunks.
+//!
+//! All integers are little-endian:
+//!
+//! ```text
+//! magic     b"LOXC"
+//! version   u16
+//! constants u32 count, then per constant a u8 tag and its payload:
+//!             0 = integer (i64), 1 = float (f64 bits), 2 = string (u32 length + UTF-8)
+//! code      u32 length + bytes
+//! lines     u32 count, then (u32 offset, u32 line) pairs
+//! checksum  u32 FNV-1a of every preceding byte
+//! ```
+//!
+//! Loading checks the header, the checksum and that the bytecode is
+//! well-formed, so the VM never runs a truncated or hand-edited file. The
+//! VM trusts what it loads: every path through the bytecode must keep its
+//! stacks from underflowing, the way the compiler's output does.
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    token::LiteralValue,
+};
+use anyhow::{bail, ensure, Context};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bumped whenever the layout or the opcode numbering changes.
+pub const VERSION: u16 = 3;
+
+const TAG_INT: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_STRING: u8 = 2;
+
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(chunk: &Chunk) -> anyhow::Result<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    out.extend(VERSION.to_le_bytes());
+
+    write_len(&mut out, chunk.constants.len())?;
+    for constant in &chunk.constants {
+        match constant {
+            LiteralValue::IntValue(i) => {
+                out.push(TAG_INT);
+                out.extend(i.to_le_bytes());
+            }
+            LiteralValue::FValue(f) => {
+                out.push(TAG_FLOAT);
+                out.extend(f.to_bits().to_le_bytes());
+            }
+            LiteralValue::StringValue(s) => {
+                out.push(TAG_STRING);
+                write_len(&mut out, s.len())?;
+                out.extend(s.as_bytes());
+            }
+            _ => bail!("Constant '{constant}' cannot be serialized"),
+        }
+    }
+
+    write_len(&mut out, chunk.code.len())?;
+    out.extend(&chunk.code);
+
+    write_len(&mut out, chunk.lines.len())?;
+    for &(offset, line) in &chunk.lines {
+        write_len(&mut out, offset)?;
+        write_len(&mut out, line)?;
+    }
+
+    let checksum = fnv1a(&out);
+    out.extend(checksum.to_le_bytes());
+
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> anyhow::Result<Chunk> {
+    ensure!(is_compiled(bytes), "Not a compiled Lox file");
+    ensure!(
+        bytes.len() >= MAGIC.len() + 2 + 4,
+        "Compiled file is truncated"
+    );
+
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    let mut reader = Reader {
+        bytes: body,
+        position: MAGIC.len(),
+    };
+
+    let version = u16::from_le_bytes(reader.array()?);
+    ensure!(
+        version == VERSION,
+        "Compiled with format version {version}, but this rlox reads version {VERSION}"
+    );
+    ensure!(
+        fnv1a(body).to_le_bytes() == checksum,
+        "Compiled file is corrupt (checksum mismatch)"
+    );
+
+    let mut chunk = Chunk::default();
+
+    for _ in 0..reader.len()? {
+        let constant = match reader.byte()? {
+            TAG_INT => LiteralValue::IntValue(i64::from_le_bytes(reader.array()?)),
+            TAG_FLOAT => LiteralValue::FValue(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+            TAG_STRING => {
+                let len = reader.len()?;
+                let s =
+                    std::str::from_utf8(reader.take(len)?).context("Invalid string constant")?;
+                LiteralValue::StringValue(s.into())
+            }
+            tag => bail!("Unknown constant tag {tag}"),
+        };
+        chunk.constants.push(constant);
+    }
+
+    let len = reader.len()?;
+    chunk.code = reader.take(len)?.to_vec();
+
+    for _ in 0..reader.len()? {
+        chunk.lines.push((reader.len()?, reader.len()?));
+    }
+
+    ensure!(
+        reader.position == body.len(),
+        "Unexpected trailing data in compiled file"
+    );
+
+    verify(&chunk)?;
+
+    Ok(chunk)
+}
+
+/// Checks that every instruction decodes and stays inside the chunk, then
+/// that running it cannot underflow the VM's stacks.
+fn verify(chunk: &Chunk) -> anyhow::Result<()> {
+    let code = &chunk.code;
+    ensure!(
+        code.last() == Some(&(OpCode::Return as u8)),
+        "Bytecode must end with Return"
+    );
+    ensure!(
+        chunk.lines.windows(2).all(|pair| pair[0].0 < pair[1].0)
+            && chunk.lines.iter().all(|&(offset, _)| offset < code.len()),
+        "Malformed line table"
+    );
+
+    // Where each instruction starts, so jumps can be checked to land on one.
+    let mut starts = vec![false; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        starts[offset] = true;
+        let op = OpCode::try_from(code[offset])
+            .map_err(|byte| anyhow::anyhow!("Unknown opcode {byte} at offset {offset}"))?;
+        let next = offset + 1 + op.operand_width();
+        ensure!(next <= code.len(), "Truncated {op:?} at offset {offset}");
+
+        match op {
+            OpCode::Constant => {
+                let index = chunk.read_u16(offset + 1) as usize;
+                ensure!(
+                    index < chunk.constants.len(),
+                    "Constant {index} out of range at offset {offset}"
+                );
+            }
+
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::Invoke
+            | OpCode::CallNative => {
+                let index = chunk.read_u16(offset + 1) as usize;
+                ensure!(
+                    matches!(
+                        chunk.constants.get(index),
+                        Some(LiteralValue::StringValue(_))
+                    ),
+                    "Name constant {index} invalid at offset {offset}"
+                );
+            }
+
+            OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::IterNext
+            | OpCode::PushCatch
+            | OpCode::PushFinally => {
+                let target = next + chunk.read_u16(offset + 1) as usize;
+                ensure!(target < code.len(), "Jump out of range at offset {offset}");
+            }
+
+            OpCode::Loop => {
+                let distance = chunk.read_u16(offset + 1) as usize;
+                ensure!(distance <= next, "Loop out of range at offset {offset}");
+            }
+
+            _ => {}
+        }
+
+        offset = next;
+    }
+
+    verify_stacks(chunk, &starts)
+}
+
+/// What is known about the VM before an instruction: the height of the
+/// value stack, the number of errors set aside by `finally` clauses, and
+/// the same two heights for each active `try` handler, innermost last.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Heights {
+    stack: usize,
+    pending: usize,
+    handlers: Vec<(usize, usize)>,
+}
+
+/// Follows every path through the bytecode, checking that no instruction
+/// pops more values than are there, reads a missing local slot or rethrows
+/// without a pending error, and that paths which meet agree on the heights.
+///
+/// An error unwinds by truncating the stacks to the heights its handler
+/// recorded, so nothing may pop below those while the handler is active;
+/// otherwise the handler would resume with less than it expects.
+fn verify_stacks(chunk: &Chunk, starts: &[bool]) -> anyhow::Result<()> {
+    let code = &chunk.code;
+    let mut paths = Paths {
+        starts,
+        seen: vec![None; code.len()],
+        queue: Vec::new(),
+    };
+    paths.reach(0, 0, Heights::default())?;
+
+    while let Some(offset) = paths.queue.pop() {
+        let mut heights = paths.seen[offset].clone().expect("queued offsets are seen");
+        let op = OpCode::try_from(code[offset]).expect("instructions were decoded");
+        let next = offset + 1 + op.operand_width();
+        let (floor, pending_floor) = heights.handlers.last().copied().unwrap_or_default();
+
+        let (pops, pushes) = match op {
+            OpCode::Constant | OpCode::Nil | OpCode::True | OpCode::False | OpCode::GetGlobal => {
+                (0, 1)
+            }
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::Print
+            | OpCode::IterStart
+            | OpCode::Throw => (1, 0),
+            OpCode::SetGlobal
+            | OpCode::Negate
+            | OpCode::Not
+            | OpCode::CheckBool
+            | OpCode::JumpIfFalse => (1, 1),
+            OpCode::GetLocal | OpCode::SetLocal => {
+                let slot = code[offset + 1] as usize;
+                ensure!(
+                    slot < heights.stack,
+                    "Local slot {slot} out of range at offset {offset}"
+                );
+                (usize::from(op == OpCode::SetLocal), 1)
+            }
+            OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Range
+            | OpCode::RangeInclusive
+            | OpCode::Index => (2, 1),
+            OpCode::SetIndex => (3, 1),
+            OpCode::BuildList => (chunk.read_u16(offset + 1) as usize, 1),
+            OpCode::BuildMap => (chunk.read_u16(offset + 1) as usize * 2, 1),
+            OpCode::Invoke => (code[offset + 3] as usize + 1, 1),
+            OpCode::CallNative => (code[offset + 3] as usize, 1),
+            OpCode::GetProperty
+            | OpCode::Call
+            | OpCode::Jump
+            | OpCode::Loop
+            | OpCode::IterNext
+            | OpCode::PushCatch
+            | OpCode::PushFinally
+            | OpCode::PopHandler
+            | OpCode::Rethrow
+            | OpCode::Return => (0, 0),
+        };
+        ensure!(
+            heights.stack >= floor + pops,
+            "Stack underflow at offset {offset}"
+        );
+        heights.stack = heights.stack - pops + pushes;
+
+        match op {
+            OpCode::Jump | OpCode::Loop => {
+                paths.reach(offset, jump_target(chunk, offset, op), heights)?
+            }
+            OpCode::JumpIfFalse => {
+                paths.reach(offset, jump_target(chunk, offset, op), heights.clone())?;
+                paths.reach(offset, next, heights)?;
+            }
+            // Pushes the next value, or jumps without one.
+            OpCode::IterNext => {
+                paths.reach(offset, jump_target(chunk, offset, op), heights.clone())?;
+                heights.stack += 1;
+                paths.reach(offset, next, heights)?;
+            }
+            OpCode::PushCatch | OpCode::PushFinally => {
+                let catch = op == OpCode::PushCatch;
+                let handler = Heights {
+                    stack: heights.stack + usize::from(catch),
+                    pending: heights.pending + usize::from(!catch),
+                    handlers: heights.handlers.clone(),
+                };
+                paths.reach(offset, jump_target(chunk, offset, op), handler)?;
+                heights.handlers.push((heights.stack, heights.pending));
+                paths.reach(offset, next, heights)?;
+            }
+            OpCode::PopHandler => {
+                heights.handlers.pop();
+                paths.reach(offset, next, heights)?;
+            }
+            OpCode::Rethrow => ensure!(
+                heights.pending > pending_floor,
+                "Rethrow without a pending error at offset {offset}"
+            ),
+            // These always leave the chunk or raise an error.
+            OpCode::Throw | OpCode::GetProperty | OpCode::Call | OpCode::Return => {}
+            _ => paths.reach(offset, next, heights)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// The instructions `verify_stacks` has reached and those left to follow.
+struct Paths<'a> {
+    starts: &'a [bool],
+    seen: Vec<Option<Heights>>,
+    queue: Vec<usize>,
+}
+
+impl Paths<'_> {
+    /// Records that control passes from `from` to `target` with `heights`.
+    fn reach(&mut self, from: usize, target: usize, heights: Heights) -> anyhow::Result<()> {
+        ensure!(
+            self.starts.get(target) == Some(&true),
+            "No instruction at offset {target}, reached from offset {from}"
+        );
+
+        match &self.seen[target] {
+            Some(seen) => ensure!(
+                *seen == heights,
+                "Stack heights disagree at offset {target}"
+            ),
+            None => {
+                self.seen[target] = Some(heights);
+                self.queue.push(target);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the jump at `offset` lands.
+fn jump_target(chunk: &Chunk, offset: usize, op: OpCode) -> usize {
+    let next = offset + 1 + op.operand_width();
+    let distance = chunk.read_u16(offset + 1) as usize;
+    match op {
+        OpCode::Loop => next - distance,
+        _ => next + distance,
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> anyhow::Result<()> {
+    let len = u32::try_from(len).context("Chunk too large to serialize")?;
+    out.extend(len.to_le_bytes());
+    Ok(())
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .context("Compiled file is truncated")?;
+
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> anyhow::Result<usize> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, VERSION};
+    use crate::{
+        chunk::{Chunk, OpCode},
+        testing::compile,
+    };
+
+    #[test]
+    fn round_trip() {
+        let chunk = compile("var a = 1.5;\nfor (x in [1, \"two\"]) print x;\nprint {\"k\": a};");
+
+        assert_eq!(decode(&encode(&chunk).unwrap()).unwrap(), chunk);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bytes = encode(&compile("print 1 + 2;")).unwrap();
+
+        let mut corrupt = bytes.clone();
+        corrupt[8] ^= 0xff;
+        assert!(decode(&corrupt)
+            .unwrap_err()
+            .to_string()
+            .contains("checksum"));
+
+        let mut future = bytes.clone();
+        future[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(decode(&future).unwrap_err().to_string().contains("version"));
+
+        assert!(decode(&bytes[..bytes.len() - 6]).is_err());
+        assert!(decode(b"print 1;").is_err());
+
+        // A well-formed file whose bytecode jumps past the end.
+        let mut chunk = compile("print 1;");
+        chunk
+            .code
+            .splice(0..0, [crate::chunk::OpCode::Jump as u8, 0xff, 0xff]);
+        let bad_jump = encode(&chunk).unwrap();
+        assert!(decode(&bad_jump).unwrap_err().to_string().contains("Jump"));
+    }
+
+    #[test]
+    fn accepts_every_script() {
+        let scripts = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+
+        for entry in std::fs::read_dir(scripts).unwrap() {
+            let path = entry.unwrap().path();
+            let chunk = compile(&std::fs::read_to_string(&path).unwrap());
+            assert!(
+                decode(&encode(&chunk).unwrap()).is_ok(),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unsafe_bytecode() {
+        let error = |code: &[OpCode], operands: &[(usize, u8)]| {
+            let mut chunk = Chunk {
+                code: code.iter().map(|&op| op as u8).collect(),
+                constants: vec![crate::token::LiteralValue::IntValue(1)],
+                ..Chunk::default()
+            };
+            for &(offset, byte) in operands {
+                chunk.code.insert(offset, byte);
+            }
+            decode(&encode(&chunk).unwrap()).unwrap_err().to_string()
+        };
+        use OpCode::*;
+
+        assert!(error(&[Pop, Return], &[]).contains("underflow"));
+        assert!(error(&[GetLocal, Return], &[(1, 200)]).contains("slot 200"));
+        assert!(error(&[Rethrow, Return], &[]).contains("Rethrow"));
+        // Jumping onto the operand of `Constant`.
+        assert!(error(
+            &[Jump, Constant, Print, Return],
+            &[(1, 0), (2, 1), (4, 0), (5, 0)]
+        )
+        .contains("No instruction at offset 4"));
+        // One path reaches `Return` with an extra value.
+        assert!(error(&[True, JumpIfFalse, Nil, Return], &[(2, 0), (3, 1)]).contains("disagree"));
+        // A caught error would resume with the stack below what it expects.
+        assert!(error(
+            &[Nil, PushCatch, Pop, Nil, Throw, Pop, Return],
+            &[(2, 0), (3, 3)]
+        )
+        .contains("underflow"));
+    }
+}
@@ -0,0 +1,708 @@
+//! `rlox lsp`: a Language Server Protocol server on stdin and stdout.
+//!
+//! Documents are synced in full and analysed again on every change: the
+//! scanner, parser and resolver run with their errors collected as
+//! diagnostics, then `Index` records every declaration and every name that
+//! refers to one.
+
+use crate::{
+    native,
+    parser::Parser,
+    resolver::{Record, Resolver},
+    scanner::{Scanner, KEYWORDS},
+    token::Token,
+    token_type::TokenType,
+    Collected, ErrorMsg,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+/// A line and byte column, both counted from 1 as in `Token`.
+type Position = (usize, usize);
+
+/// A JSON-RPC error code and message.
+type Failure = (i64, String);
+
+// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// LSP enumerations.
+const SEVERITY_ERROR: u8 = 1;
+const SYMBOL_VARIABLE: u8 = 13;
+const SYMBOL_CONSTANT: u8 = 14;
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_KEYWORD: u8 = 14;
+const COMPLETION_CONSTANT: u8 = 21;
+
+/// Serves requests until the client sends `exit`. The process should exit
+/// with the returned status.
+pub fn run() -> io::Result<i32> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut server = Server::default();
+
+    while let Some(body) = read_message(&mut input)? {
+        let message = match serde_json::from_slice::<Value>(&body) {
+            Ok(message) => message,
+            Err(error) => {
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": PARSE_ERROR, "message": error.to_string() },
+                });
+                write_message(&mut output, &response)?;
+                continue;
+            }
+        };
+
+        if message["method"] == "exit" {
+            return Ok(if server.shut_down { 0 } else { 1 });
+        }
+
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+
+    // The client went away without `exit`.
+    Ok(1)
+}
+
+/// The body of the next `Content-Length` framed message, or `None` at the
+/// end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without Content-Length",
+        ));
+    };
+
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+impl Server {
+    /// Handles one message, returning the response to a request and any
+    /// notifications to send.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params).into_iter().collect();
+        };
+
+        let response = match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+
+        vec![response]
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, Failure> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // Every change sends the whole document.
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "rlox", "version": env!("CARGO_PKG_VERSION") },
+            })),
+
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+
+            "textDocument/definition" => {
+                let (uri, document, position) = self.position(params)?;
+                Ok(match document.symbol_at(position) {
+                    Some(declaration) => document.location(uri, &declaration.name),
+                    None => Value::Null,
+                })
+            }
+
+            "textDocument/references" => {
+                let (uri, document, position) = self.position(params)?;
+                let Some(id) = document.index.id_at(position) else {
+                    return Ok(Value::Null);
+                };
+
+                let declaration = &document.index.declarations[id].name;
+                let include_declaration = params["context"]["includeDeclaration"] == true;
+                let mut tokens: Vec<_> = include_declaration
+                    .then_some(declaration)
+                    .into_iter()
+                    .chain(document.references_to(id))
+                    .collect();
+                tokens.sort_by_key(|token| (token.line, token.column));
+
+                let locations: Vec<_> = tokens
+                    .into_iter()
+                    .map(|token| document.location(uri, token))
+                    .collect();
+                Ok(json!(locations))
+            }
+
+            "textDocument/hover" => {
+                let (_, document, position) = self.position(params)?;
+                Ok(match document.symbol_at(position) {
+                    Some(declaration) => json!({
+                        "contents": { "kind": "markdown", "value": document.describe(declaration) },
+                    }),
+                    None => Value::Null,
+                })
+            }
+
+            "textDocument/documentSymbol" => {
+                let (_, document) = self.document(params)?;
+                let symbols: Vec<_> = document
+                    .index
+                    .declarations
+                    .iter()
+                    .filter(|declaration| declaration.global)
+                    .map(|declaration| {
+                        let kind = match declaration.kind {
+                            TokenType::Var => SYMBOL_VARIABLE,
+                            _ => SYMBOL_CONSTANT,
+                        };
+                        json!({
+                            "name": declaration.name.lexeme.as_str(),
+                            "kind": kind,
+                            "range": document.range(&declaration.name),
+                            "selectionRange": document.range(&declaration.name),
+                        })
+                    })
+                    .collect();
+                Ok(json!(symbols))
+            }
+
+            "textDocument/completion" => {
+                let (_, document, position) = self.position(params)?;
+                Ok(json!(document.completions(position)))
+            }
+
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method '{method}'"))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?.to_string();
+
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str()?,
+            // Full sync: the last change holds the whole text.
+            "textDocument/didChange" => {
+                params["contentChanges"].as_array()?.last()?["text"].as_str()?
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return Some(diagnostics(&uri, Vec::new()));
+            }
+            _ => return None,
+        };
+
+        let document = Document::new(text.to_string());
+        let published = diagnostics(&uri, document.diagnostics());
+        self.documents.insert(uri, document);
+        Some(published)
+    }
+
+    fn document<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a Document), Failure> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "Missing textDocument.uri".to_string()))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Document {uri} is not open")))?;
+        Ok((uri, document))
+    }
+
+    fn position<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Result<(&'a str, &'a Document, Position), Failure> {
+        let (uri, document) = self.document(params)?;
+        let position = &params["position"];
+        let (Some(line), Some(character)) =
+            (position["line"].as_u64(), position["character"].as_u64())
+        else {
+            return Err((INVALID_PARAMS, "Missing position".to_string()));
+        };
+
+        Ok((
+            uri,
+            document,
+            document.source_position(line as usize, character as usize),
+        ))
+    }
+}
+
+fn diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// An open file and what is known about it.
+struct Document {
+    source: String,
+    // Byte offset where each line starts.
+    lines: Vec<usize>,
+    tokens: Vec<Token>,
+    errors: Vec<Collected>,
+    index: Index,
+}
+
+impl Document {
+    fn new(source: String) -> Self {
+        let ((tokens, index), mut errors) = ErrorMsg::collect(|| {
+            let mut scanner = Scanner::new(&source);
+            let tokens = scanner.scan_tokens().to_vec();
+            let stmts = Parser::new(tokens.clone()).parse();
+            let (_, record) = Resolver::new().record(&stmts);
+            (tokens, Index::new(record))
+        });
+
+        // The resolver's errors come after all the parser's.
+        errors.sort_by_key(|error| error.line);
+
+        let lines = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        let mut document = Self {
+            source,
+            lines,
+            tokens,
+            errors,
+            index,
+        };
+        let ends = (0..document.index.declarations.len())
+            .map(|id| document.scope_end(id))
+            .collect::<Vec<_>>();
+        for (declaration, end) in document.index.declarations.iter_mut().zip(ends) {
+            declaration.scope_end = end;
+        }
+        document
+    }
+
+    fn line_text(&self, line: usize) -> &str {
+        let Some(&start) = self.lines.get(line.wrapping_sub(1)) else {
+            return "";
+        };
+        let end = self
+            .lines
+            .get(line)
+            .map_or(self.source.len(), |end| end - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    /// An LSP position for a line and byte column, both counted from 1.
+    fn lsp_position(&self, line: usize, column: usize) -> Value {
+        let text = self.line_text(line);
+        let byte = column.saturating_sub(1).min(text.len());
+        let character = text
+            .get(..byte)
+            .map_or(byte, |text| text.encode_utf16().count());
+        json!({ "line": line.saturating_sub(1), "character": character })
+    }
+
+    /// The line and byte column, counted from 1, of an LSP position.
+    fn source_position(&self, line: usize, character: usize) -> Position {
+        let text = self.line_text(line + 1);
+        let mut units = 0;
+        let byte = text
+            .char_indices()
+            .find(|(_, c)| {
+                units += c.len_utf16();
+                units > character
+            })
+            .map_or(text.len(), |(byte, _)| byte);
+        (line + 1, byte + 1)
+    }
+
+    fn range(&self, token: &Token) -> Value {
+        json!({
+            "start": self.lsp_position(token.line, token.column),
+            "end": self.lsp_position(token.line, token.column + token.lexeme.len()),
+        })
+    }
+
+    fn location(&self, uri: &str, token: &Token) -> Value {
+        json!({ "uri": uri, "range": self.range(token) })
+    }
+
+    fn diagnostics(&self) -> Vec<Value> {
+        self.errors
+            .iter()
+            .map(|error| {
+                let range = match &error.token {
+                    Some(token) => self.range(token),
+                    // Scanner errors know only their line.
+                    None => json!({
+                        "start": self.lsp_position(error.line, 1),
+                        "end": self.lsp_position(error.line, self.line_text(error.line).len() + 1),
+                    }),
+                };
+                json!({
+                    "range": range,
+                    "severity": SEVERITY_ERROR,
+                    "source": "rlox",
+                    "message": error.message,
+                })
+            })
+            .collect()
+    }
+
+    /// The declaration of the name under the cursor.
+    fn symbol_at(&self, position: Position) -> Option<&Declaration> {
+        self.index
+            .id_at(position)
+            .map(|id| &self.index.declarations[id])
+    }
+
+    fn references_to(&self, id: usize) -> impl Iterator<Item = &Token> {
+        self.index
+            .references
+            .iter()
+            .filter(move |(_, declaration)| *declaration == id)
+            .map(|(token, _)| token)
+    }
+
+    /// Hover text: the declaring line and what kind of name it is.
+    fn describe(&self, declaration: &Declaration) -> String {
+        let what = match (&declaration.kind, declaration.global) {
+            (TokenType::Var, true) => "Global variable",
+            (TokenType::Var, false) => "Local variable",
+            (TokenType::Const | TokenType::Let, true) => "Global constant",
+            (TokenType::Const | TokenType::Let, false) => "Local constant",
+            (TokenType::For, _) => "Loop variable",
+            _ => "Caught exception",
+        };
+        format!(
+            "```lox\n{}\n```\n{what}, declared on line {}.",
+            self.line_text(declaration.name.line).trim(),
+            declaration.name.line
+        )
+    }
+
+    /// Keywords, natives and the names in scope at `position`, innermost
+    /// first.
+    fn completions(&self, position: Position) -> Vec<Value> {
+        let mut seen = Vec::<&str>::new();
+        let mut items = Vec::new();
+
+        for declaration in self.index.declarations.iter().rev() {
+            let name = declaration.name.lexeme.as_str();
+            let start = (declaration.name.line, declaration.name.column);
+            let visible = declaration.name.token_type == TokenType::Identifier
+                && start < position
+                && declaration.scope_end.is_none_or(|end| position <= end);
+            if !visible || seen.contains(&name) {
+                continue;
+            }
+
+            seen.push(name);
+            let kind = match declaration.kind {
+                TokenType::Const | TokenType::Let => COMPLETION_CONSTANT,
+                _ => COMPLETION_VARIABLE,
+            };
+            items.push(json!({ "label": name, "kind": kind }));
+        }
+
+        items.extend(
+            native::NATIVES
+                .iter()
+                .map(|name| json!({ "label": name, "kind": COMPLETION_FUNCTION })),
+        );
+
+        let mut keywords: Vec<_> = KEYWORDS.keys().collect();
+        keywords.sort();
+        items.extend(
+            keywords
+                .into_iter()
+                .map(|keyword| json!({ "label": keyword, "kind": COMPLETION_KEYWORD })),
+        );
+
+        items
+    }
+
+    /// Where the scope of a local declaration closes, found in the tokens
+    /// since blocks keep no position in the tree. `None` for globals.
+    ///
+    /// A `var` in a block lasts to the `}` closing that block. A name
+    /// declared in the parentheses of `for (...)` or `catch (...)` lasts to
+    /// the end of the body that follows: its block, or its `;`. One that is
+    /// a whole branch or loop body ends with its own `;`.
+    fn scope_end(&self, id: usize) -> Option<Position> {
+        let declaration = &self.index.declarations[id];
+        if declaration.global {
+            return None;
+        }
+
+        let name = &declaration.name;
+        let start = self
+            .tokens
+            .iter()
+            .position(|token| (token.line, token.column) == (name.line, name.column))?;
+
+        if declaration.branch {
+            let mut depth = 0;
+            for token in &self.tokens[start..] {
+                match token.token_type {
+                    TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => {
+                        depth += 1
+                    }
+                    TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                        depth -= 1
+                    }
+                    TokenType::Semicolon if depth == 0 => return Some((token.line, token.column)),
+                    TokenType::Eof => return Some((token.line, token.column)),
+                    _ => {}
+                }
+            }
+        }
+
+        let (mut parens, mut braces, mut in_body) = (0, 0, false);
+        for token in &self.tokens[start..] {
+            let end = Some((token.line, token.column));
+            match token.token_type {
+                TokenType::LeftParen => parens += 1,
+                TokenType::RightParen if parens == 0 => in_body = true,
+                TokenType::RightParen => parens -= 1,
+                TokenType::LeftBrace => braces += 1,
+                TokenType::RightBrace if braces == 0 => return end,
+                TokenType::RightBrace => {
+                    braces -= 1;
+                    if in_body && braces == 0 {
+                        return end;
+                    }
+                }
+                TokenType::Semicolon if in_body && parens == 0 && braces == 0 => return end,
+                TokenType::Eof => return end,
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+/// A name introduced by `var`, `const`, `let`, `for` or `catch`.
+struct Declaration {
+    name: Token,
+    /// The declaring keyword: `Var`, `Const`, `Let`, `For` or `Catch`.
+    kind: TokenType,
+    global: bool,
+    /// Declared as the whole body of a branch or loop in a block, where it
+    /// is scoped to that body.
+    branch: bool,
+    /// Last position where the name is in scope; see `Document::scope_end`.
+    scope_end: Option<Position>,
+}
+
+/// Declarations and the names referring to them, as `Resolver` found
+/// them.
+struct Index {
+    declarations: Vec<Declaration>,
+    // Each variable read or assigned, with the declaration it refers to.
+    references: Vec<(Token, usize)>,
+}
+
+impl Index {
+    fn new(record: Record) -> Self {
+        let declarations = record
+            .declarations
+            .into_iter()
+            .map(|declared| Declaration {
+                global: declared.is_global(),
+                name: declared.name,
+                kind: declared.kind,
+                branch: declared.branch,
+                scope_end: None,
+            })
+            .collect();
+        let references = record
+            .uses
+            .into_iter()
+            .map(|used| (used.name, used.declaration))
+            .collect();
+
+        Self {
+            declarations,
+            references,
+        }
+    }
+
+    /// The declaration named at `position`, by its declaring token or by
+    /// a reference to it.
+    fn id_at(&self, (line, column): Position) -> Option<usize> {
+        // After a syntax error the parser may have declared another token.
+        let under = |token: &Token| {
+            token.token_type == TokenType::Identifier
+                && token.line == line
+                && (token.column..=token.column + token.lexeme.len()).contains(&column)
+        };
+
+        self.declarations
+            .iter()
+            .position(|declaration| under(&declaration.name))
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|(token, _)| under(token))
+                    .map(|(_, id)| *id)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Document, Position};
+
+    // Cursor on the `n`th occurrence of `name`, as a line and column.
+    fn at(document: &Document, name: &str, n: usize) -> Position {
+        let offset = document.source.match_indices(name).nth(n).unwrap().0;
+        let line = document
+            .lines
+            .iter()
+            .rposition(|&start| start <= offset)
+            .unwrap();
+        (line + 1, offset - document.lines[line] + 1)
+    }
+
+    #[test]
+    fn resolves_names_by_scope() {
+        let document = Document::new(
+            "var total = 0;\n{\n  var total = 1;\n  total = total + 1;\n}\nprint total;\n"
+                .to_string(),
+        );
+
+        let inner = document.symbol_at(at(&document, "total", 2)).unwrap();
+        assert_eq!((inner.name.line, inner.global), (3, false));
+        assert_eq!(
+            document
+                .references_to(document.index.id_at(at(&document, "total", 3)).unwrap())
+                .count(),
+            2
+        );
+
+        let outer = document.symbol_at(at(&document, "total", 4)).unwrap();
+        assert_eq!((outer.name.line, outer.global), (1, true));
+        assert!(document
+            .describe(outer)
+            .starts_with("```lox\nvar total = 0;\n```\nGlobal variable"));
+    }
+
+    #[test]
+    fn completes_names_in_scope() {
+        let document =
+            Document::new("var a = 1;\nfor (x in [1]) {\n  var b = x;\n  \n}\n\n".to_string());
+        let labels = |position| -> Vec<String> {
+            document
+                .completions(position)
+                .iter()
+                .map(|item| item["label"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let inside = labels((4, 3));
+        assert_eq!(inside[..3], ["b", "x", "a"]);
+        assert!(inside.contains(&"while".to_string()));
+
+        let after = labels((6, 1));
+        assert_eq!(after[0], "a");
+        assert!(!after.contains(&"b".to_string()));
+    }
+
+    #[test]
+    fn scopes_branch_declarations() {
+        let document = Document::new("{\n  if (true) var x = {};\n  x;\n}\n".to_string());
+
+        let declaration = document.index.id_at(at(&document, "x", 0)).unwrap();
+        assert_eq!(
+            document.index.declarations[declaration].scope_end,
+            Some((2, 23))
+        );
+        assert_eq!(document.index.id_at(at(&document, "x", 1)), None);
+    }
+
+    #[test]
+    fn reports_errors_as_diagnostics() {
+        let document = Document::new("const a = 1;\na = 2;\nprint (1;\nprint \"open".to_string());
+        let diagnostics = document.diagnostics();
+
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+        assert!(diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("constant 'a'"));
+        assert_eq!(diagnostics[1]["range"]["start"]["line"], 2);
+        assert!(diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic["message"] == "Unterminated string"));
+    }
+
+    #[test]
+    fn reports_oversized_integers() {
+        let document = Document::new("var x = 99999999999999999999;\n".to_string());
+        let diagnostics = document.diagnostics();
+
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+        assert_eq!(diagnostics[0]["message"], "Integer literal too large.");
+        assert_eq!(diagnostics.len(), 1);
+    }
+}
@@ -1,62 +1,557 @@
+mod chunk;
+mod compiler;
+mod coverage;
+mod debugger;
+mod disassembler;
+mod error;
 mod expr;
+mod formatter;
+mod gc;
+mod intern;
 mod interpret;
+mod iter;
+mod limits;
+mod lint;
+mod list;
+mod loxc;
+mod lsp;
+mod map;
+mod native;
+mod optimizer;
 mod parser;
+mod profiler;
+mod repl;
+mod resolver;
 mod scanner;
+#[cfg(test)]
+mod testing;
 mod token;
 mod token_type;
+mod trace;
+mod vm;
 
 use anyhow::Context;
+use compiler::Compiler;
+use coverage::Coverage;
+use debugger::Debugger;
+use error::LoxError;
 use interpret::{Environment, Interpret};
-use parser::Parser;
+use limits::{Limits, STACK_SIZE};
+use parser::{Parser, Stmt};
+use profiler::Profiler;
+use repl::Command;
+use resolver::Resolver;
+use rustyline::{error::ReadlineError, DefaultEditor};
 use scanner::Scanner;
 use std::cell::RefCell;
-use std::io::Write;
 use std::rc::Rc;
-use std::{env, fs::File, io::Read, path::Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{
+    env,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
 use token::Token;
 use token_type::TokenType;
+use trace::{Frame, Trace};
+use vm::Vm;
+
+const USAGE: &str = "\
+Usage: rlox [-O] [--vm] [--dump-tokens] [--dump-ast] [--dump-bytecode] [--gc-stress] [--gc-stats]
+            [--profile] [--profile-folded=FILE] [--coverage] [--coverage-file=FILE] [--max-steps=N] [--timeout=MS] [--max-depth=N] [--max-heap=BYTES] [script | file.loxc]
+       rlox compile <script> [-o <file.loxc>]
+       rlox fmt [--check] <script>...
+       rlox lint [--json] <script>...
+       rlox lsp
+       rlox debug <script>";
+
+/// Prints `USAGE` and exits with 64.
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(64)
+}
+
+/// Set by `ErrorMsg::report` whenever a compile-time error is printed.
+static HAD_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Set by `ErrorMsg::runtime`; a script that fails exits with 70.
+static HAD_RUNTIME_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Which engine executes a parsed program.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    #[default]
+    TreeWalker,
+    /// Compile to bytecode and run it on the stack VM (`--vm`).
+    Vm,
+}
+
+/// Intermediate forms printed instead of running the program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dump {
+    /// `--dump-tokens`: the scanner's token stream.
+    Tokens,
+    /// `--dump-ast`: the parsed statements.
+    Ast,
+    /// `--dump-bytecode`: the disassembled chunk.
+    Bytecode,
+}
 
 #[derive(Default)]
 struct Lox {
     had_error: bool,
+    backend: Backend,
+    dumps: Vec<Dump>,
+    /// `--gc-stats`: report collector activity once the script finishes.
+    gc_stats: bool,
+    /// `--profile`: time each line and call, and report once the script
+    /// finishes. Tree walker only.
+    profile: bool,
+    /// `--profile-folded=FILE`: also write the profile as folded stacks.
+    folded: Option<PathBuf>,
+    /// `--coverage`: the lcov file each script run appends its line and
+    /// branch counts to. Tree walker only.
+    coverage: Option<PathBuf>,
+    /// `-O`: run the constant-folding pass before resolving.
+    optimize: bool,
+    limits: Limits,
+    /// Reading from the prompt: a lone expression prints its value.
+    repl: bool,
+    /// `fmt --check`: report files that are not formatted, change nothing.
+    check: bool,
+    /// `lint --json`: print warnings as a JSON array.
+    json: bool,
 }
 
 impl Lox {
     fn run_file(&self, path: &Path, env: Rc<RefCell<Environment>>) -> anyhow::Result<()> {
-        let mut content = String::default();
-        Read::read_to_string(
+        let mut content = Vec::default();
+        Read::read_to_end(
             &mut File::open(path).with_context(|| format!("Path: {:?}", path))?,
             &mut content,
         )?;
 
-        run(content, env);
+        if loxc::is_compiled(&content) {
+            let chunk = loxc::decode(&content).with_context(|| format!("Path: {:?}", path))?;
+            if self.dumps.contains(&Dump::Bytecode) {
+                print!("{}", disassembler::disassemble(&chunk, "script"));
+            } else {
+                let mut vm = Vm::new().with_limits(self.limits);
+                if let Err(error) = vm.interpret(&chunk, env) {
+                    ErrorMsg::runtime(&error, vm.frames());
+                    std::process::exit(70);
+                }
+            }
+            return Ok(());
+        }
+
+        let content = String::from_utf8(content).with_context(|| format!("Path: {:?}", path))?;
+        self.run(content, Some(path), env);
 
         if self.had_error {
             std::process::exit(64);
         }
+        if HAD_ERROR.load(Ordering::Relaxed) {
+            std::process::exit(65);
+        }
+        if HAD_RUNTIME_ERROR.load(Ordering::Relaxed) {
+            std::process::exit(70);
+        }
+
+        Ok(())
+    }
+
+    /// Runs `source`, read from the script at `path` if there is one.
+    fn run(&self, source: String, path: Option<&Path>, env: Rc<RefCell<Environment>>) {
+        let mut scanner = Scanner::new(&source);
+        let tokens = scanner.scan_tokens().to_vec();
+
+        if self.dumps.contains(&Dump::Tokens) {
+            for token in &tokens {
+                println!("{token}");
+            }
+        }
+
+        let mut stmts = self.parse(tokens);
+
+        if let [Stmt::Expression(_)] = stmts.as_slice() {
+            if self.repl {
+                let Some(Stmt::Expression(expr)) = stmts.pop() else {
+                    unreachable!()
+                };
+                stmts.push(Stmt::Print(expr));
+            }
+        }
+
+        if self.dumps.contains(&Dump::Ast) {
+            for stmt in &stmts {
+                println!("{stmt}");
+            }
+        }
+
+        if HAD_ERROR.load(Ordering::Relaxed) || !Resolver::new().resolve(&stmts) {
+            return;
+        }
+
+        if self.dumps.contains(&Dump::Bytecode) {
+            if let Some(chunk) = Compiler::new().compile(&stmts) {
+                print!("{}", disassembler::disassemble(&chunk, "script"));
+            }
+        }
+
+        if !self.dumps.is_empty() {
+            return;
+        }
+
+        match self.backend {
+            Backend::TreeWalker => {
+                let mut interpret = Interpret::new().with_limits(self.limits);
+                if self.profile {
+                    interpret = interpret.with_profiler(Profiler::new(&source));
+                }
+                if self.coverage.is_some() && path.is_some() {
+                    interpret = interpret.with_coverage(Coverage::new(&stmts));
+                }
+
+                let result = interpret.interpret(&stmts, env);
+                if let Some(profiler) = interpret.take_profiler() {
+                    self.report(&profiler);
+                }
+                if let (Some(coverage), Some(path)) = (interpret.take_coverage(), path) {
+                    self.write_coverage(&coverage, path);
+                }
+                if let Err(error) = result {
+                    ErrorMsg::runtime(&error, interpret.frames());
+                }
+            }
+            Backend::Vm => {
+                if let Some(chunk) = Compiler::new().compile(&stmts) {
+                    let mut vm = Vm::new().with_limits(self.limits);
+                    if let Err(error) = vm.interpret(&chunk, env) {
+                        ErrorMsg::runtime(&error, vm.frames());
+                    }
+                }
+            }
+        }
+    }
+
+    fn report(&self, profiler: &Profiler) {
+        eprint!("{}", profiler.report());
+
+        if let Some(path) = &self.folded {
+            if let Err(error) = fs::write(path, profiler.folded()) {
+                eprintln!("Cannot write {}: {error}", path.display());
+            }
+        }
+    }
+
+    // Appends, so that every script in a suite adds to one file.
+    fn write_coverage(&self, coverage: &Coverage, script: &Path) {
+        let Some(output) = &self.coverage else {
+            return;
+        };
+        let script = fs::canonicalize(script).unwrap_or_else(|_| script.to_path_buf());
+
+        let written = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output)
+            .and_then(|mut file| {
+                std::io::Write::write_all(&mut file, coverage.lcov(&script).as_bytes())
+            });
+        if let Err(error) = written {
+            eprintln!("Cannot write {}: {error}", output.display());
+        }
+    }
+
+    fn parse(&self, tokens: Vec<Token>) -> Vec<Stmt> {
+        let parser = Parser::new(tokens);
+        let stmts = if self.repl {
+            parser.repl().parse()
+        } else {
+            parser.parse()
+        };
+
+        if self.optimize {
+            optimizer::optimize(stmts)
+        } else {
+            stmts
+        }
+    }
+
+    /// Compiles `source` to bytecode and writes it to `output` as a `.loxc`
+    /// file. Nothing is written if the script has errors.
+    fn compile(&self, source: &Path, output: &Path) -> anyhow::Result<()> {
+        let content = fs::read_to_string(source).with_context(|| format!("Path: {:?}", source))?;
+
+        let mut scanner = Scanner::new(&content);
+        let stmts = self.parse(scanner.scan_tokens().to_vec());
+        let chunk = Resolver::new()
+            .resolve(&stmts)
+            .then(|| Compiler::new().compile(&stmts))
+            .flatten();
+
+        match chunk {
+            Some(chunk) if !HAD_ERROR.load(Ordering::Relaxed) => {
+                fs::write(output, loxc::encode(&chunk)?)
+                    .with_context(|| format!("Path: {:?}", output))?;
+                Ok(())
+            }
+            _ => std::process::exit(65),
+        }
+    }
+
+    fn command(&self, command: Command, env: &Rc<RefCell<Environment>>) {
+        match command {
+            Command::Help => println!("{}", repl::HELP),
+            Command::Vars => {
+                for (name, value, constant) in env.borrow().globals() {
+                    let keyword = if constant { "const" } else { "var" };
+                    println!("{keyword} {name} = {value}");
+                }
+            }
+            Command::Reset => *env.borrow_mut() = Environment::new(),
+            Command::Load(path) => match fs::read_to_string(&path) {
+                Ok(source) => self.run(source, Some(&path), env.clone()),
+                Err(error) => eprintln!("Cannot read {}: {error}", path.display()),
+            },
+            Command::Ast(source) => {
+                let mut scanner = Scanner::new(&source);
+                for stmt in self.parse(scanner.scan_tokens().to_vec()) {
+                    println!("{stmt}");
+                }
+            }
+            Command::Time(source) => {
+                let start = Instant::now();
+                self.run(source, None, env.clone());
+                eprintln!("[time] {:.3} ms", start.elapsed().as_secs_f64() * 1000.0);
+            }
+        }
+        HAD_ERROR.store(false, Ordering::Relaxed);
+    }
+
+    /// Rewrites each file in the canonical style, or with `--check` only
+    /// lists those that would change. Exits with 1 if any would, or 65 if
+    /// any has syntax errors.
+    fn format(&self, paths: &[String]) -> anyhow::Result<()> {
+        let mut status = 0;
+
+        for path in paths {
+            let source = fs::read_to_string(path).with_context(|| format!("Path: {:?}", path))?;
+            HAD_ERROR.store(false, Ordering::Relaxed);
+            let formatted = formatter::format(&source);
+            if HAD_ERROR.load(Ordering::Relaxed) {
+                status = 65;
+                continue;
+            }
+
+            match formatted {
+                Ok(formatted) if formatted == source => {}
+                Ok(_) if self.check => {
+                    eprintln!("Would reformat {path}");
+                    status = status.max(1);
+                }
+                Ok(formatted) => {
+                    fs::write(path, formatted).with_context(|| format!("Path: {:?}", path))?
+                }
+                Err(message) => {
+                    eprintln!("{path}: {message}");
+                    status = 65;
+                }
+            }
+        }
+
+        if status != 0 {
+            std::process::exit(status);
+        }
+        Ok(())
+    }
+
+    /// Prints the lint warnings for each file, one per line or as a single
+    /// JSON array. Exits with 1 if there are any, or 65 if any file has
+    /// errors.
+    fn lint(&self, paths: &[String]) -> anyhow::Result<()> {
+        let mut status = 0;
+        let mut json = Vec::new();
+
+        for path in paths {
+            let source = fs::read_to_string(path).with_context(|| format!("Path: {:?}", path))?;
+            HAD_ERROR.store(false, Ordering::Relaxed);
+            let warnings = lint::lint(&source);
+            if HAD_ERROR.load(Ordering::Relaxed) {
+                status = 65;
+                continue;
+            }
+
+            match warnings {
+                Ok(warnings) => {
+                    if !warnings.is_empty() {
+                        status = status.max(1);
+                    }
+                    for warning in warnings {
+                        if self.json {
+                            json.push(warning.to_json(path));
+                        } else {
+                            println!(
+                                "{path}:{}: {} [{}]",
+                                warning.line, warning.message, warning.rule
+                            );
+                        }
+                    }
+                }
+                Err(message) => {
+                    eprintln!("{path}: {message}");
+                    status = 65;
+                }
+            }
+        }
 
+        if self.json {
+            match json.as_slice() {
+                [] => println!("[]"),
+                _ => println!("[\n  {}\n]", json.join(",\n  ")),
+            }
+        }
+        if status != 0 {
+            std::process::exit(status);
+        }
         Ok(())
     }
 
+    /// Runs a script on the tree walker under the debugger, reading its
+    /// commands from the terminal.
+    fn debug(&self, path: &Path, env: Rc<RefCell<Environment>>) -> anyhow::Result<()> {
+        let source = fs::read_to_string(path).with_context(|| format!("Path: {:?}", path))?;
+        let mut scanner = Scanner::new(&source);
+        let stmts = self.parse(scanner.scan_tokens().to_vec());
+        if HAD_ERROR.load(Ordering::Relaxed) || !Resolver::new().resolve(&stmts) {
+            std::process::exit(65);
+        }
+
+        let mut editor = DefaultEditor::new()?;
+        let debugger = Debugger::new(&source, move || match editor.readline("(debug) ") {
+            Ok(line) => {
+                let _ = editor.add_history_entry(&line);
+                Some(line)
+            }
+            Err(ReadlineError::Interrupted) => Some("quit".to_string()),
+            Err(_) => None,
+        });
+        println!("Debugging {}; type 'help' for commands.", path.display());
+
+        let mut interpret = Interpret::new()
+            .with_limits(self.limits)
+            .with_debugger(debugger);
+        match interpret.interpret(&stmts, env) {
+            Ok(()) | Err(LoxError::Stopped { .. }) => {}
+            Err(error) => ErrorMsg::runtime(&error, interpret.frames()),
+        }
+        Ok(())
+    }
+
+    /// Reads statements until EOF. Input with an open bracket or string
+    /// continues on the next line, under a `...` prompt.
     fn run_prompt(&mut self, env: Rc<RefCell<Environment>>) {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(error) => {
+                eprintln!("Cannot start the prompt: {error}");
+                return;
+            }
+        };
+
+        self.repl = true;
+        let history = repl::history_path();
+        if let Some(path) = &history {
+            // Missing on the first run.
+            let _ = editor.load_history(path);
+        }
+
+        let mut source = String::new();
         loop {
-            let _ = std::io::stdout().write(b"> ");
-            let _ = std::io::stdout().flush();
+            let prompt = if source.is_empty() { "> " } else { "... " };
 
-            let mut line = String::default();
-            let _ = std::io::stdin().read_line(&mut line);
+            match editor.readline(prompt) {
+                Ok(line) if source.is_empty() && line.starts_with(':') => {
+                    let _ = editor.add_history_entry(&line);
+                    match Command::parse(&line) {
+                        Ok(command) => self.command(command, &env),
+                        Err(message) => eprintln!("{message}"),
+                    }
+                }
+                Ok(line) => {
+                    source.push_str(&line);
+                    source.push('\n');
+                    if repl::is_incomplete(&source) {
+                        continue;
+                    }
 
-            // println!("{}", line);
-            run(line, env.clone());
-            self.had_error = false;
+                    let _ = editor.add_history_entry(source.trim_end());
+                    self.run(std::mem::take(&mut source), None, env.clone());
+                    self.had_error = false;
+                    HAD_ERROR.store(false, Ordering::Relaxed);
+                }
+                // Ctrl-C abandons the input typed so far.
+                Err(ReadlineError::Interrupted) => source.clear(),
+                // Ctrl-D, or stdin closed.
+                Err(_) => break,
+            }
+        }
+
+        if let Some(path) = &history {
+            if let Err(error) = editor.save_history(path) {
+                eprintln!("Cannot save history to {}: {error}", path.display());
+            }
         }
     }
 }
 
 pub struct ErrorMsg;
 
+/// A compile-time error kept by `ErrorMsg::collect` instead of printed.
+#[derive(Debug, Clone)]
+pub struct Collected {
+    pub line: usize,
+    /// The token the error is about, when there is one.
+    pub token: Option<Token>,
+    pub message: String,
+}
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Vec<Collected>>> = const { RefCell::new(None) };
+}
+
 impl ErrorMsg {
+    /// Runs `f`, returning the compile-time errors it reported rather than
+    /// printing them. `HAD_ERROR` is left alone.
+    pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<Collected>) {
+        let outer = COLLECTED.replace(Some(Vec::new()));
+        let value = f();
+        let errors = COLLECTED.replace(outer).unwrap_or_default();
+        (value, errors)
+    }
+
+    // Whether the error went to a `collect` in progress.
+    fn collected(line: usize, token: Option<&Token>, msg: &str) -> bool {
+        COLLECTED.with_borrow_mut(|collected| match collected {
+            Some(errors) => {
+                errors.push(Collected {
+                    line,
+                    token: token.cloned(),
+                    message: msg.to_string(),
+                });
+                true
+            }
+            None => false,
+        })
+    }
+
     pub fn error(token: &Token, msg: &str) {
+        if Self::collected(token.line, Some(token), msg) {
+            return;
+        }
         if token.token_type == TokenType::Eof {
             Self::report(token.line, " at end ", msg);
         }
@@ -68,36 +563,123 @@ impl ErrorMsg {
     }
 
     pub fn report(line: usize, wh: &str, msg: &str) {
+        if Self::collected(line, None, msg) {
+            return;
+        }
+        HAD_ERROR.store(true, Ordering::Relaxed);
         eprintln!("[line {line}] Error {wh}: {msg}");
     }
+
+    /// Prints `error` followed by its stack trace through `frames`, the
+    /// calls active when it was raised.
+    pub fn runtime(error: &LoxError, frames: &[Frame]) {
+        HAD_RUNTIME_ERROR.store(true, Ordering::Relaxed);
+        eprint!("{}", Trace::new(error, frames));
+    }
 }
 
-fn run(source: String, env: Rc<RefCell<Environment>>) {
-    let mut scanner = Scanner::new(&source);
+fn main() -> anyhow::Result<()> {
+    let lox = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .context("Could not start the interpreter thread")?;
+    lox.join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
 
-    let parser = Parser::new(scanner.scan_tokens().to_vec());
-    let stmts = parser.parse();
+fn run() -> anyhow::Result<()> {
+    let env = Rc::new(RefCell::new(Environment::new()));
+    let mut lox = Lox::default();
+    let (flags, args): (Vec<String>, Vec<String>) = env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--") || arg == "-O");
 
-    let mut interpret = Interpret::new();
-    interpret.interpret(&stmts, env);
+    for flag in flags {
+        if let Some(path) = flag.strip_prefix("--profile-folded=") {
+            lox.profile = true;
+            lox.folded = Some(PathBuf::from(path));
+            continue;
+        }
+        if let Some(path) = flag.strip_prefix("--coverage-file=") {
+            lox.coverage = Some(PathBuf::from(path));
+            continue;
+        }
+        if let Some((name, value)) = flag.split_once('=') {
+            let Ok(value) = value.parse::<u64>() else {
+                eprintln!("Invalid value for {name}: {value}");
+                std::process::exit(64)
+            };
 
-    // for stmt in stmts {
-    //     println!("ECHO: {}", stmt.evaluate().to_string());
-    // }
-    // println!("ECHO: {}", exprs.evaluate().to_string());
-}
+            match name {
+                "--max-steps" => lox.limits.max_steps = Some(value),
+                "--timeout" => lox.limits.timeout = Some(Duration::from_millis(value)),
+                "--max-depth" => lox.limits.max_depth = Some(value as usize),
+                "--max-heap" => lox.limits.max_heap_bytes = Some(value as usize),
+                _ => {
+                    eprintln!("Unknown option: {flag}");
+                    usage()
+                }
+            }
+            continue;
+        }
 
-fn main() -> anyhow::Result<()> {
-    let env = Rc::new(RefCell::new(Environment::new(None)));
-    let mut lox = Lox::default();
-    let args: Vec<String> = env::args().collect();
+        match flag.as_str() {
+            "-O" => lox.optimize = true,
+            "--vm" => lox.backend = Backend::Vm,
+            "--dump-tokens" => lox.dumps.push(Dump::Tokens),
+            "--dump-ast" => lox.dumps.push(Dump::Ast),
+            "--dump-bytecode" => lox.dumps.push(Dump::Bytecode),
+            "--gc-stress" => gc::set_stress(true),
+            "--gc-stats" => lox.gc_stats = true,
+            "--profile" => lox.profile = true,
+            "--coverage" => {
+                lox.coverage
+                    .get_or_insert_with(|| PathBuf::from("lcov.info"));
+            }
+            "--check" => lox.check = true,
+            "--json" => lox.json = true,
+            _ => {
+                eprintln!("Unknown option: {flag}");
+                usage()
+            }
+        }
+    }
 
-    if args.len() > 2 {
+    if (lox.profile || lox.coverage.is_some() || lox.limits.max_depth.is_some())
+        && lox.backend == Backend::Vm
+    {
+        eprintln!("--profile, --coverage and --max-depth run on the tree walker; drop --vm.");
         std::process::exit(64)
-    } else if args.len() == 2 {
-        lox.run_file(&Path::new(args.get(1).unwrap()), env.clone())?;
-    } else {
-        let _ = lox.run_prompt(env.clone());
+    }
+
+    match args.first().map(String::as_str) {
+        Some("compile") => {
+            let (source, output) = match &args[1..] {
+                [source] => (source, PathBuf::from(source).with_extension("loxc")),
+                [source, flag, output] if flag == "-o" => (source, PathBuf::from(output)),
+                _ => usage(),
+            };
+            lox.compile(Path::new(source), &output)?;
+        }
+        Some("fmt") if args.len() > 1 => lox.format(&args[1..])?,
+        Some("lint") if args.len() > 1 => lox.lint(&args[1..])?,
+        Some("debug") if args.len() == 2 => lox.debug(Path::new(&args[1]), env.clone())?,
+        Some("lsp") if args.len() == 1 => std::process::exit(lsp::run()?),
+        // A subcommand without its arguments, or more than one script.
+        Some("fmt" | "lint" | "debug" | "lsp") => usage(),
+        Some(_) if args.len() > 1 => usage(),
+        Some(path) => {
+            lox.run_file(Path::new(path), env.clone())?;
+
+            if lox.gc_stats {
+                let stats = gc::stats();
+                eprintln!(
+                    "[gc] {} collections, {} objects freed ({} bytes), {} live",
+                    stats.collections, stats.freed_objects, stats.freed_bytes, stats.live_objects
+                );
+            }
+        }
+        None => lox.run_prompt(env.clone()),
     }
 
     Ok(())
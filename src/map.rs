@@ -0,0 +1,247 @@
+use crate::{
+    error::LoxError,
+    gc,
+    intern::Symbol,
+    list::{self, arity},
+    token::{LiteralValue, Token},
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// Maps are shared by reference, like lists.
+pub type Map = Rc<RefCell<LoxMap>>;
+
+/// The hashable subset of `LiteralValue`.
+///
+/// Integers, strings, booleans and `nil` can be map keys. Floats are
+/// excluded because NaN is not equal to itself, and lists and maps because
+/// they are mutable and compared by contents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Str(Symbol),
+    Bool(bool),
+    Nil,
+}
+
+impl Key {
+    fn hashable(value: &LiteralValue) -> Option<Key> {
+        match value {
+            LiteralValue::IntValue(i) => Some(Key::Int(*i)),
+            LiteralValue::StringValue(s) => Some(Key::Str(s.to_owned())),
+            LiteralValue::True => Some(Key::Bool(true)),
+            LiteralValue::False => Some(Key::Bool(false)),
+            LiteralValue::Nil => Some(Key::Nil),
+            _ => None,
+        }
+    }
+
+    fn new(value: &LiteralValue, token: &Token) -> Result<Key, LoxError> {
+        Self::hashable(value).ok_or_else(|| {
+            LoxError::runtime(
+                token,
+                "Map keys must be integers, strings, booleans or nil.",
+            )
+        })
+    }
+}
+
+/// Key-value pairs kept in insertion order.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(LiteralValue, LiteralValue)>,
+    index: HashMap<Key, usize>,
+}
+
+impl LoxMap {
+    pub fn insert(
+        &mut self,
+        key: LiteralValue,
+        value: LiteralValue,
+        token: &Token,
+    ) -> Result<(), LoxError> {
+        let hashed = Key::new(&key, token)?;
+
+        match self.index.get(&hashed) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.index.insert(hashed, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(
+        &self,
+        key: &LiteralValue,
+        token: &Token,
+    ) -> Result<Option<&LiteralValue>, LoxError> {
+        let position = self.index.get(&Key::new(key, token)?);
+
+        Ok(position.map(|&position| &self.entries[position].1))
+    }
+
+    pub fn remove(
+        &mut self,
+        key: &LiteralValue,
+        token: &Token,
+    ) -> Result<Option<LiteralValue>, LoxError> {
+        let Some(position) = self.index.remove(&Key::new(key, token)?) else {
+            return Ok(None);
+        };
+
+        let (_, value) = self.entries.remove(position);
+        for shifted in self.index.values_mut() {
+            if *shifted > position {
+                *shifted -= 1;
+            }
+        }
+
+        Ok(Some(value))
+    }
+
+    pub fn entries(&self) -> &[(LiteralValue, LiteralValue)] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<(LiteralValue, LiteralValue)> {
+        self.entries
+    }
+
+    pub fn keys(&self) -> Vec<LiteralValue> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Whether both maps have the same keys, in any order, with values that
+    /// are the same by `equal`.
+    pub fn equal(
+        &self,
+        other: &Self,
+        mut equal: impl FnMut(&LiteralValue, &LiteralValue) -> bool,
+    ) -> bool {
+        self.entries.len() == other.entries.len()
+            && self.entries.iter().all(|(key, value)| {
+                Key::hashable(key)
+                    .and_then(|key| other.index.get(&key))
+                    .is_some_and(|&position| equal(value, &other.entries[position].1))
+            })
+    }
+}
+
+pub fn new(map: LoxMap) -> LiteralValue {
+    let map = Rc::new(RefCell::new(map));
+    gc::track_map(&map);
+    LiteralValue::Map(map)
+}
+
+/// `map[key]`
+pub fn get(map: &Map, key: &LiteralValue, bracket: &Token) -> Result<LiteralValue, LoxError> {
+    map.borrow()
+        .get(key, bracket)?
+        .cloned()
+        .ok_or_else(|| missing(key, bracket))
+}
+
+/// `map[key] = value`
+pub fn set(
+    map: &Map,
+    key: LiteralValue,
+    value: LiteralValue,
+    bracket: &Token,
+) -> Result<LiteralValue, LoxError> {
+    map.borrow_mut().insert(key, value.clone(), bracket)?;
+    gc::grow(2);
+
+    Ok(value)
+}
+
+pub fn call_method(
+    map: &Map,
+    name: &Token,
+    arguments: Vec<LiteralValue>,
+) -> Result<LiteralValue, LoxError> {
+    match name.lexeme.as_str() {
+        "has" => {
+            let [key] = arity(name, arguments)?;
+            let found = map.borrow().get(&key, name)?.is_some();
+            Ok(if found {
+                LiteralValue::True
+            } else {
+                LiteralValue::False
+            })
+        }
+
+        "remove" => {
+            let [key] = arity(name, arguments)?;
+            let removed = map.borrow_mut().remove(&key, name)?;
+            removed.ok_or_else(|| missing(&key, name))
+        }
+
+        "keys" => {
+            let [] = arity(name, arguments)?;
+            Ok(list::new(map.borrow().keys()))
+        }
+
+        "values" => {
+            let [] = arity(name, arguments)?;
+            let values = map
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(_, v)| v.clone())
+                .collect();
+            Ok(list::new(values))
+        }
+
+        "len" => {
+            let [] = arity(name, arguments)?;
+            Ok(LiteralValue::IntValue(map.borrow().entries().len() as i64))
+        }
+
+        _ => Err(LoxError::runtime(
+            name,
+            format!("Undefined method '{}' on map.", name.lexeme),
+        )),
+    }
+}
+
+fn missing(key: &LiteralValue, token: &Token) -> LoxError {
+    match key {
+        LiteralValue::StringValue(s) => LoxError::runtime(token, format!("Key {s:?} not found.")),
+        _ => LoxError::runtime(token, format!("Key {key} not found.")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::eval;
+
+    #[test]
+    fn literal_index_and_assign() {
+        assert_eq!(
+            eval("var result = {\"b\": 1, 2: true}; result[\"a\"] = nil; result[2] = 3;"),
+            "{\"b\": 1, 2: 3, \"a\": nil}"
+        );
+        assert_eq!(eval("var result = {\"a\": [1]}[\"a\"][0];"), "1");
+        assert_eq!(eval("var result = {}[\"a\"];"), "Key \"a\" not found.");
+        assert_eq!(
+            eval("var result = {[1]: 1};"),
+            "Map keys must be integers, strings, booleans or nil."
+        );
+    }
+
+    #[test]
+    fn block_is_not_a_map() {
+        assert_eq!(eval("var result = 1; { result = 2; }"), "2");
+        assert_eq!(eval("var result = 1; {}"), "1");
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(
+            eval("var m = {1: 2, 3: 4}; var result = [m.has(1), m.remove(1), m.has(1), m.keys(), m.values()];"),
+            "[true, 2, false, [3], [4]]"
+        );
+    }
+}
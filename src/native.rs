@@ -0,0 +1,32 @@
+//! Built-in functions called by name, such as `gc()`.
+//!
+//! Natives are not values: a call whose callee is a global name listed here
+//! runs the native directly, in both backends.
+
+use crate::{
+    error::LoxError,
+    gc,
+    list::arity,
+    token::{LiteralValue, Token},
+};
+
+pub const NATIVES: &[&str] = &["gc"];
+
+pub fn exists(name: &str) -> bool {
+    NATIVES.contains(&name)
+}
+
+pub fn call(name: &Token, arguments: Vec<LiteralValue>) -> Result<LiteralValue, LoxError> {
+    match name.lexeme.as_str() {
+        // Collects unreachable lists and maps; returns the bytes freed.
+        "gc" => {
+            let [] = arity(name, arguments)?;
+            Ok(LiteralValue::IntValue(gc::collect() as i64))
+        }
+
+        _ => Err(LoxError::runtime(
+            name,
+            format!("Undefined function '{}'.", name.lexeme),
+        )),
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{
+    expr::{self, Expr},
+    parser::Stmt,
+    token::LiteralValue,
+    token_type::TokenType,
+};
+
+/// Optional AST pass enabled with `-O`, run before the resolver.
+///
+/// Folds operators whose operands are all literals and drops branches and
+/// loops whose condition is a literal. Folding uses the same semantics
+/// functions as both backends, and an operation that would fail (division
+/// by zero, mismatched operands) is left in place so the error still
+/// happens at runtime, on the same line.
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts
+        .into_iter()
+        .map(stmt)
+        .filter(|s| !is_empty(s))
+        .collect()
+}
+
+fn stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Expression(e) => Stmt::Expression(expr(e)),
+        Stmt::Print(e) => Stmt::Print(expr(e)),
+
+        Stmt::Variable { keyword, bindings } => Stmt::Variable {
+            keyword,
+            bindings: bindings
+                .into_iter()
+                .map(|mut binding| {
+                    binding.initializer = binding.initializer.map(expr);
+                    binding
+                })
+                .collect(),
+        },
+
+        Stmt::Block(stmts) => Stmt::Block(optimize(stmts)),
+
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let condition = expr(condition);
+            let then_branch = Box::new(self::stmt(*then_branch));
+            let else_branch = else_branch.map(|branch| Box::new(self::stmt(*branch)));
+
+            let taken = match &condition {
+                Expr::Literal { value, .. } if *value == LiteralValue::True => Some(&then_branch),
+                Expr::Literal { .. } => else_branch.as_ref(),
+                _ => None,
+            };
+
+            // A bare declaration as a branch is scoped differently once it
+            // is hoisted out of the `if`, so those are kept.
+            if !matches!(condition, Expr::Literal { .. }) || taken.is_some_and(|b| declares(b)) {
+                return Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                };
+            }
+
+            match condition {
+                Expr::Literal {
+                    value: LiteralValue::True,
+                    ..
+                } => *then_branch,
+                _ => else_branch.map_or_else(empty, |branch| *branch),
+            }
+        }
+
+        Stmt::While {
+            expr: condition,
+            stmt: body,
+        } => {
+            let condition = expr(condition);
+            if never_true(&condition) {
+                return empty();
+            }
+
+            Stmt::While {
+                expr: condition,
+                stmt: Box::new(self::stmt(*body)),
+            }
+        }
+
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        } => {
+            let condition = condition.map(expr);
+            if initializer.is_none() && condition.as_ref().is_some_and(never_true) {
+                return empty();
+            }
+
+            Stmt::For {
+                initializer: initializer.map(|initializer| Box::new(self::stmt(*initializer))),
+                condition,
+                increment: increment.map(expr),
+                body: Box::new(self::stmt(*body)),
+            }
+        }
+
+        Stmt::ForIn {
+            name,
+            keyword,
+            iterable,
+            body,
+            slot,
+        } => Stmt::ForIn {
+            name,
+            keyword,
+            iterable: expr(iterable),
+            body: Box::new(self::stmt(*body)),
+            slot,
+        },
+
+        Stmt::Throw { keyword, value } => Stmt::Throw {
+            keyword,
+            value: expr(value),
+        },
+
+        Stmt::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        } => Stmt::Try {
+            keyword,
+            body: Box::new(self::stmt(*body)),
+            catch: catch.map(|mut catch| {
+                catch.body = Box::new(self::stmt(*catch.body));
+                catch
+            }),
+            finally: finally.map(|finally| Box::new(self::stmt(*finally))),
+        },
+    }
+}
+
+fn expr(e: Expr) -> Expr {
+    match e {
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (expr(*left), expr(*right));
+
+            if let (Expr::Literal { value: a, line }, Expr::Literal { value: b, .. }) =
+                (&left, &right)
+            {
+                if let Ok(value) = expr::binary(&operator, a.clone(), b.clone()) {
+                    return Expr::Literal { value, line: *line };
+                }
+            }
+
+            Expr::Binary {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }
+        }
+
+        Expr::Unary {
+            operator,
+            expression,
+        } => {
+            let expression = expr(*expression);
+
+            if let Expr::Literal { value, .. } = &expression {
+                if let Ok(value) = expr::unary(&operator, value.clone()) {
+                    return Expr::Literal {
+                        value,
+                        line: operator.line,
+                    };
+                }
+            }
+
+            Expr::Unary {
+                operator,
+                expression: Box::new(expression),
+            }
+        }
+
+        Expr::Grouping { expression } => match expr(*expression) {
+            literal @ Expr::Literal { .. } => literal,
+            expression => Expr::Grouping {
+                expression: Box::new(expression),
+            },
+        },
+
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (expr(*left), expr(*right));
+            let short_circuit = match operator.token_type {
+                TokenType::Or => LiteralValue::True,
+                _ => LiteralValue::False,
+            };
+
+            match (&left, &right) {
+                // `false and x` / `true or x` never look at `x`.
+                (Expr::Literal { value, .. }, _) if *value == short_circuit => left,
+                // Otherwise the result is `x`, which must itself be a boolean.
+                (Expr::Literal { value: a, .. }, Expr::Literal { value: b, .. })
+                    if is_boolean(a) && is_boolean(b) =>
+                {
+                    right
+                }
+                _ => Expr::Logical {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+            }
+        }
+
+        Expr::Range {
+            start,
+            operator,
+            end,
+        } => {
+            let (start, end) = (expr(*start), expr(*end));
+
+            if let (Expr::Literal { value: a, line }, Expr::Literal { value: b, .. }) =
+                (&start, &end)
+            {
+                if let Ok(value) = expr::range(&operator, a.clone(), b.clone()) {
+                    return Expr::Literal { value, line: *line };
+                }
+            }
+
+            Expr::Range {
+                start: Box::new(start),
+                operator,
+                end: Box::new(end),
+            }
+        }
+
+        Expr::Assign { name, value, slot } => Expr::Assign {
+            name,
+            value: Box::new(expr(*value)),
+            slot,
+        },
+
+        // Lists and maps are mutable, so each evaluation must build a new one.
+        Expr::List { elements } => Expr::List {
+            elements: elements.into_iter().map(expr).collect(),
+        },
+
+        Expr::Map { brace, entries } => Expr::Map {
+            brace,
+            entries: entries
+                .into_iter()
+                .map(|(key, value)| (expr(key), expr(value)))
+                .collect(),
+        },
+
+        Expr::Index {
+            object,
+            bracket,
+            index,
+        } => Expr::Index {
+            object: Box::new(expr(*object)),
+            bracket,
+            index: Box::new(expr(*index)),
+        },
+
+        Expr::SetIndex {
+            object,
+            bracket,
+            index,
+            value,
+        } => Expr::SetIndex {
+            object: Box::new(expr(*object)),
+            bracket,
+            index: Box::new(expr(*index)),
+            value: Box::new(expr(*value)),
+        },
+
+        Expr::Get { object, name } => Expr::Get {
+            object: Box::new(expr(*object)),
+            name,
+        },
+
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => Expr::Call {
+            callee: Box::new(expr(*callee)),
+            paren,
+            arguments: arguments.into_iter().map(expr).collect(),
+        },
+
+        Expr::Literal { .. } | Expr::Variable { .. } => e,
+    }
+}
+
+/// Conditions only run their body when they evaluate to exactly `true`.
+fn never_true(condition: &Expr) -> bool {
+    matches!(condition, Expr::Literal { value, .. } if *value != LiteralValue::True)
+}
+
+fn is_boolean(value: &LiteralValue) -> bool {
+    matches!(value, LiteralValue::True | LiteralValue::False)
+}
+
+fn declares(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::Variable { .. })
+}
+
+fn empty() -> Stmt {
+    Stmt::Block(Vec::new())
+}
+
+fn is_empty(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::Block(stmts) if stmts.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::optimize;
+    use crate::testing::parse;
+
+    fn optimized(source: &str) -> String {
+        let stmts = parse(source);
+
+        optimize(stmts)
+            .iter()
+            .map(|stmt| stmt.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(optimized("print 1 + 2 * 3;"), "(print 7)");
+        assert_eq!(optimized("print \"a\" + \"b\" == \"ab\";"), "(print true)");
+        assert_eq!(optimized("print -(2.5) < 0.0 and !false;"), "(print true)");
+        assert_eq!(optimized("var r = 1..=3;"), "(var (r 1..=3))");
+        assert_eq!(optimized("print x + (1 + 1);"), "(print (+ x 2))");
+    }
+
+    #[test]
+    fn keeps_runtime_errors() {
+        assert_eq!(optimized("print 1 / 0;"), "(print (/ 1 0))");
+        assert_eq!(optimized("print 1 + \"a\";"), "(print (+ 1 \"a\"))");
+        assert_eq!(optimized("print true and 1;"), "(print (and true 1))");
+    }
+
+    #[test]
+    fn drops_dead_branches() {
+        assert_eq!(optimized("if (true) print 1; else print 2;"), "(print 1)");
+        assert_eq!(optimized("if (1 > 2) print 1;"), "");
+        assert_eq!(optimized("while (false) print 1; print 2;"), "(print 2)");
+        assert_eq!(optimized("for (; 1 == 2;) print 1;"), "");
+    }
+}
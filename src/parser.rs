@@ -1,14 +1,25 @@
 use crate::{
     expr::Expr,
+    limits::MAX_NESTING,
+    resolver::Slot,
     token::{LiteralValue, Token},
     token_type::TokenType,
     ErrorMsg,
 };
+use std::fmt;
 
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    /// Statements and operators open around the token being parsed, which
+    /// bounds how deep the tree gets.
+    depth: usize,
+    /// Set once nesting passes `MAX_NESTING`; the rest of the input is
+    /// skipped and no further errors are reported.
+    aborted: bool,
+    /// Input typed at the prompt may leave out the final semicolon.
+    repl: bool,
     // env: &'a mut Environment,
 }
 
@@ -18,9 +29,10 @@ pub enum Stmt {
     Print(Expr),
     Block(Vec<Stmt>),
 
+    /// A `var`, `const` or `let` declaration; `keyword` tells them apart.
     Variable {
-        token: Token,
-        expression: Expr,
+        keyword: Token,
+        bindings: Vec<Binding>,
     },
 
     If {
@@ -35,22 +47,206 @@ pub enum Stmt {
     },
 
     For {
-        initializer: Expr,
-        condition: Expr,
-        increment: Expr,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+
+    /// `for (name in iterable) body`; `keyword` is the `in` token.
+    ForIn {
+        name: Token,
+        keyword: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+        slot: Slot,
+    },
+
+    /// `throw value;`
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+
+    /// `try body catch (name) body finally body`, with at least one of
+    /// the two clauses. Each body is a block.
+    Try {
+        keyword: Token,
         body: Box<Stmt>,
+        catch: Option<Catch>,
+        finally: Option<Box<Stmt>>,
     },
 }
 
+impl Stmt {
+    /// Line where the statement starts, when any token in it records one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression(expr) | Stmt::Print(expr) => expr.line(),
+            Stmt::Block(stmts) => stmts.iter().find_map(Stmt::line),
+            Stmt::Variable { keyword, .. } => Some(keyword.line),
+            Stmt::If { condition, .. } => condition.line(),
+            Stmt::While { expr, .. } => expr.line(),
+            Stmt::For {
+                initializer,
+                condition,
+                body,
+                ..
+            } => initializer
+                .as_ref()
+                .and_then(|initializer| initializer.line())
+                .or_else(|| condition.as_ref().and_then(Expr::line))
+                .or_else(|| body.line()),
+            Stmt::ForIn { name, .. } => Some(name.line),
+            Stmt::Throw { keyword, .. } | Stmt::Try { keyword, .. } => Some(keyword.line),
+        }
+    }
+
+    // Nested blocks go on their own lines, indented by `depth`.
+    fn fmt_depth(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let optional = |expr: &Option<Expr>| match expr {
+            Some(expr) => expr.to_string(),
+            None => "_".to_string(),
+        };
+
+        match self {
+            Stmt::Expression(expr) => write!(f, "(expr {})", expr),
+            Stmt::Print(expr) => write!(f, "(print {})", expr),
+
+            Stmt::Block(stmts) => {
+                write!(f, "(block")?;
+                for stmt in stmts {
+                    write!(f, "\n{}", "  ".repeat(depth + 1))?;
+                    stmt.fmt_depth(f, depth + 1)?;
+                }
+                write!(f, ")")
+            }
+
+            Stmt::Variable { keyword, bindings } => {
+                write!(f, "({}", keyword.lexeme)?;
+                for binding in bindings {
+                    match &binding.initializer {
+                        Some(initializer) => {
+                            write!(f, " ({} {})", binding.token.lexeme, initializer)?
+                        }
+                        None => write!(f, " {}", binding.token.lexeme)?,
+                    }
+                }
+                write!(f, ")")
+            }
+
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                write!(f, "(if {} ", condition)?;
+                then_branch.fmt_depth(f, depth)?;
+                if let Some(else_branch) = else_branch {
+                    write!(f, " ")?;
+                    else_branch.fmt_depth(f, depth)?;
+                }
+                write!(f, ")")
+            }
+
+            Stmt::While { expr, stmt } => {
+                write!(f, "(while {} ", expr)?;
+                stmt.fmt_depth(f, depth)?;
+                write!(f, ")")
+            }
+
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                write!(f, "(for ")?;
+                match initializer {
+                    Some(initializer) => initializer.fmt_depth(f, depth)?,
+                    None => write!(f, "_")?,
+                }
+                write!(f, " {} {} ", optional(condition), optional(increment))?;
+                body.fmt_depth(f, depth)?;
+                write!(f, ")")
+            }
+
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                write!(f, "(for-in {} {} ", name.lexeme, iterable)?;
+                body.fmt_depth(f, depth)?;
+                write!(f, ")")
+            }
+
+            Stmt::Throw { value, .. } => write!(f, "(throw {})", value),
+
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                write!(f, "(try ")?;
+                body.fmt_depth(f, depth)?;
+                if let Some(catch) = catch {
+                    write!(f, " (catch {} ", catch.name.lexeme)?;
+                    catch.body.fmt_depth(f, depth)?;
+                    write!(f, ")")?;
+                }
+                if let Some(finally) = finally {
+                    write!(f, " (finally ")?;
+                    finally.fmt_depth(f, depth)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_depth(f, 0)
+    }
+}
+
+/// A single `name [= initializer]` entry of a `var` declaration.
+#[derive(Debug)]
+pub struct Binding {
+    pub token: Token,
+    pub initializer: Option<Expr>,
+    pub slot: Slot,
+}
+
+/// The `catch (name) body` clause of a `try` statement.
+#[derive(Debug)]
+pub struct Catch {
+    pub name: Token,
+    pub body: Box<Stmt>,
+    pub slot: Slot,
+}
+
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
         Self {
             tokens,
             current: 0,
+            depth: 0,
+            aborted: false,
+            repl: false,
             // env,
         }
     }
 
+    pub fn repl(mut self) -> Self {
+        self.repl = true;
+        self
+    }
+
     pub fn parse(mut self) -> Vec<Stmt> {
         let mut stmts = vec![];
 
@@ -62,6 +258,18 @@ impl Parser {
     }
 
     fn statement(&mut self) -> Stmt {
+        let depth = self.depth;
+        let stmt = if self.nest() {
+            self.statement_kind()
+        } else {
+            Stmt::Block(vec![])
+        };
+        self.depth = depth;
+
+        stmt
+    }
+
+    fn statement_kind(&mut self) -> Stmt {
         if self.match_token([TokenType::Print]) {
             return self.print_stmt();
         }
@@ -70,16 +278,18 @@ impl Parser {
             return self.if_stmt();
         }
 
-        if self.match_token([TokenType::LeftBrace]) {
-            let mut statements = vec![];
-
-            while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-                statements.push(self.declaration());
-            }
+        // A `{` opening a statement is a block unless it starts a map literal.
+        if self.check(TokenType::LeftBrace) && !self.map_ahead() {
+            self.advance();
+            return self.block();
+        }
 
-            self.consume(TokenType::RightBrace, "Expect } after block");
+        if self.match_token([TokenType::Throw]) {
+            return self.throw_stmt();
+        }
 
-            return Stmt::Block(statements);
+        if self.match_token([TokenType::Try]) {
+            return self.try_stmt();
         }
 
         if self.match_token([TokenType::While]) {
@@ -94,7 +304,7 @@ impl Parser {
     }
 
     fn declaration(&mut self) -> Stmt {
-        if self.match_token([TokenType::Var]) {
+        if self.match_token([TokenType::Var, TokenType::Const, TokenType::Let]) {
             return self.var_declaration();
         }
 
@@ -102,67 +312,121 @@ impl Parser {
     }
 
     fn for_stmt(&mut self) -> Stmt {
-        // for (var i = 0; i < 10; i=i+1 ) { print i }
-        self.consume(TokenType::LeftParen, "Expect '(' after initialization");
+        // for (var i = 0; i < 10; i = i + 1) { print i; }
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'");
+
+        // for (x in xs) { print x; }
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::In) {
+            return self.for_in_stmt();
+        }
 
         let initializer = if self.match_token([TokenType::Semicolon]) {
             None
-        } else if self.match_token([TokenType::Var]) {
-            Some(self.var_declaration())
+        } else if self.match_token([TokenType::Var, TokenType::Const, TokenType::Let]) {
+            Some(Box::new(self.var_declaration()))
         } else {
-            Some(self.expression_stmt())
+            Some(Box::new(self.expression_stmt()))
         };
 
-        // i < 10; i=i+1 ) { print i }
-
-        let condition = if self.match_token([TokenType::Semicolon]) {
+        let condition = if self.check(TokenType::Semicolon) {
             None
         } else {
             Some(self.expression())
         };
 
-        // ; i = i+1 ) { print i }
-        self.consume(TokenType::Semicolon, "Expect ';' after initialization");
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition");
 
-        // i = 1+1  ) { print i }
-        let increment = if self.match_token([TokenType::Semicolon]) {
+        let increment = if self.check(TokenType::RightParen) {
             None
         } else {
-            Some(Stmt::Expression(self.expression()))
+            Some(self.expression())
         };
 
-        self.consume(TokenType::RightParen, "Expect ')' after initialization");
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses");
 
-        // Create a while body similarly how C handles the for loop
-        let body = self.statement();
+        let body = Box::new(self.statement());
 
-        let body = if let Some(incr) = increment {
-            Stmt::Block(vec![incr, body])
-        } else {
-            body
-        };
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        }
+    }
 
-        let body = if let Some(expr) = condition {
-            Stmt::While {
-                expr,
-                stmt: Box::new(body),
-            }
-        } else {
-            Stmt::While {
-                expr: Expr::Literal {
-                    value: LiteralValue::True,
-                },
-                stmt: Box::new(body),
+    fn for_in_stmt(&mut self) -> Stmt {
+        let name = self.advance().clone();
+        let keyword = self.advance().clone();
+
+        let iterable = self.expression();
+
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses");
+
+        let body = Box::new(self.statement());
+
+        Stmt::ForIn {
+            name,
+            keyword,
+            iterable,
+            body,
+            slot: Slot::default(),
+        }
+    }
+
+    /// The rest of a block whose `{` has been consumed.
+    fn block(&mut self) -> Stmt {
+        let mut statements = vec![];
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration());
+        }
+
+        self.consume(TokenType::RightBrace, "Expect } after block");
+
+        Stmt::Block(statements)
+    }
+
+    fn throw_stmt(&mut self) -> Stmt {
+        let keyword = self.previous().clone();
+        let value = self.expression();
+        self.end_statement("Expect ';' after thrown value");
+
+        Stmt::Throw { keyword, value }
+    }
+
+    fn try_stmt(&mut self) -> Stmt {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'");
+        let body = Box::new(self.block());
+
+        let catch = self.match_token([TokenType::Catch]).then(|| {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'");
+            let name = self.consume(TokenType::Identifier, "Expect exception name");
+            self.consume(TokenType::RightParen, "Expect ')' after exception name");
+            self.consume(TokenType::LeftBrace, "Expect '{' after catch clause");
+
+            Catch {
+                name,
+                body: Box::new(self.block()),
+                slot: Slot::default(),
             }
-        };
+        });
 
-        let body = if let Some(initializer) = initializer {
-            Stmt::Block(vec![initializer, body])
-        } else {
-            body
-        };
+        let finally = self.match_token([TokenType::Finally]).then(|| {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'");
+            Box::new(self.block())
+        });
 
-        body
+        if catch.is_none() && finally.is_none() {
+            self.error(self.peek(), "Expect 'catch' or 'finally' after try block");
+        }
+
+        Stmt::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        }
     }
 
     fn while_stmt(&mut self) -> Stmt {
@@ -199,34 +463,66 @@ impl Parser {
         }
     }
 
+    /// var_decl → ( "var" | "const" | "let" ) IDENTIFIER ( "=" expression )?
+    ///            ( "," IDENTIFIER ( "=" expression )? )* ";" ;
+    ///
+    /// Constants (`const`, `let`) must be initialized.
     fn var_declaration(&mut self) -> Stmt {
-        let token = self.consume(TokenType::Identifier, "Expect identifier");
-        self.consume(TokenType::Equal, "Expect '=' after identifer");
+        let keyword = self.previous().clone();
+        let mut bindings = vec![];
+
+        loop {
+            let token = self.consume(TokenType::Identifier, "Expect identifier");
+
+            let initializer = if self.match_token([TokenType::Equal]) {
+                Some(self.expression())
+            } else {
+                if keyword.token_type != TokenType::Var {
+                    self.error(&token, "Expect '=' after constant name");
+                }
+                None
+            };
 
-        let expression = self.expression();
+            bindings.push(Binding {
+                token,
+                initializer,
+                slot: Slot::default(),
+            });
+
+            if !self.match_token([TokenType::Comma]) {
+                break;
+            }
+        }
 
-        self.consume(TokenType::Semicolon, "Expect ';' after statement");
+        self.end_statement("Expect ';' after statement");
 
-        // TODO: Handle the case to not have an expression ex: var test;
-        Stmt::Variable { token, expression }
+        Stmt::Variable { keyword, bindings }
     }
 
     fn expression_stmt(&mut self) -> Stmt {
         let expr = self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after statement");
+        self.end_statement("Expect ';' after statement");
 
         Stmt::Expression(expr)
     }
 
     fn print_stmt(&mut self) -> Stmt {
         let expr = self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after statement");
+        self.end_statement("Expect ';' after statement");
 
         Stmt::Print(expr)
     }
 
     fn expression(&mut self) -> Expr {
-        self.assignment()
+        let depth = self.depth;
+        let expr = if self.nest() {
+            self.assignment()
+        } else {
+            nil(self.peek().line)
+        };
+        self.depth = depth;
+
+        expr
     }
 
     fn assignment(&mut self) -> Expr {
@@ -234,12 +530,26 @@ impl Parser {
 
         if self.match_token([TokenType::Equal]) {
             let token = self.previous().clone();
+            if !self.nest() {
+                return expr;
+            }
             let value = self.assignment();
 
             return match expr {
-                Expr::Variable { name } => Expr::Assign {
+                Expr::Variable { name, .. } => Expr::Assign {
                     name,
                     value: Box::new(value),
+                    slot: Slot::default(),
+                },
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Expr::SetIndex {
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
                 },
                 _ => {
                     ErrorMsg::error(&token, "Invalid assignment target");
@@ -291,14 +601,17 @@ impl Parser {
 
     /// equality → comparison ( ( "!=" | "==" ) comparison )* ;
     fn equality(&mut self) -> Expr {
-        let expr = self.comparison();
+        let mut expr = self.comparison();
 
         while self.match_token([TokenType::BangEqual, TokenType::EqualEqual]) {
+            if !self.nest() {
+                break;
+            }
             let operator = self.previous().to_owned();
 
             let right = Box::new(self.comparison());
 
-            return Expr::Binary {
+            expr = Expr::Binary {
                 left: Box::new(expr),
                 operator,
                 right,
@@ -308,9 +621,9 @@ impl Parser {
         expr
     }
 
-    /// comparison → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    /// comparison → range ( ( ">" | ">=" | "<" | "<=" ) range )* ;
     fn comparison(&mut self) -> Expr {
-        let expr = self.term();
+        let mut expr = self.range();
 
         while self.match_token([
             TokenType::Greater,
@@ -318,10 +631,13 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            if !self.nest() {
+                break;
+            }
             let operator = self.previous().to_owned();
-            let right = Box::new(self.term());
+            let right = Box::new(self.range());
 
-            return Expr::Binary {
+            expr = Expr::Binary {
                 left: Box::new(expr),
                 operator,
                 right,
@@ -331,15 +647,36 @@ impl Parser {
         expr
     }
 
+    /// range → term ( ( ".." | "..=" ) term )? ;
+    fn range(&mut self) -> Expr {
+        let expr = self.term();
+
+        if self.match_token([TokenType::DotDot, TokenType::DotDotEqual]) {
+            let operator = self.previous().to_owned();
+            let end = Box::new(self.term());
+
+            return Expr::Range {
+                start: Box::new(expr),
+                operator,
+                end,
+            };
+        }
+
+        expr
+    }
+
     /// term → factor ( ( "-" | "+" ) factor )* ;
     fn term(&mut self) -> Expr {
-        let expr = self.factor();
+        let mut expr = self.factor();
 
         while self.match_token([TokenType::Minus, TokenType::Plus]) {
+            if !self.nest() {
+                break;
+            }
             let operator = self.previous().to_owned();
             let right = Box::new(self.factor());
 
-            return Expr::Binary {
+            expr = Expr::Binary {
                 left: Box::new(expr),
                 operator,
                 right,
@@ -350,12 +687,15 @@ impl Parser {
 
     /// factor → unary ( ( "/" | "*" ) unary )* ;
     fn factor(&mut self) -> Expr {
-        let expr = self.unary();
+        let mut expr = self.unary();
         while self.match_token([TokenType::Slash, TokenType::Star]) {
+            if !self.nest() {
+                break;
+            }
             let operator = self.previous().to_owned();
 
             let right = Box::from(self.unary());
-            return Expr::Binary {
+            expr = Expr::Binary {
                 left: Box::from(expr),
                 operator,
                 right,
@@ -365,10 +705,13 @@ impl Parser {
         expr
     }
 
-    /// (! | -) unary | primary
+    /// (! | -) unary | call
     fn unary(&mut self) -> Expr {
         if self.match_token([TokenType::Minus, TokenType::Bang]) {
             let operator = self.previous().clone();
+            if !self.nest() {
+                return nil(self.peek().line);
+            }
             let right = self.unary();
 
             return Expr::Unary {
@@ -377,65 +720,187 @@ impl Parser {
             };
         }
 
-        self.primary().unwrap()
+        self.call()
+    }
+
+    /// call → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
+    fn call(&mut self) -> Expr {
+        let Some(mut expr) = self.primary() else {
+            self.error(self.peek(), "Expect expression");
+            return nil(self.peek().line);
+        };
+
+        loop {
+            if self.match_token([TokenType::LeftParen]) {
+                expr = self.finish_call(expr);
+            } else if self.match_token([TokenType::Dot]) {
+                let name = self.consume(TokenType::Identifier, "Expect property name after '.'");
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else if self.match_token([TokenType::LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index = self.expression();
+                self.consume(TokenType::RightBracket, "Expect ']' after index");
+
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+            } else {
+                break;
+            }
+
+            if !self.nest() {
+                break;
+            }
+        }
+
+        expr
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Expr {
+        let arguments = self.arguments(TokenType::RightParen);
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments");
+
+        Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        }
+    }
+
+    /// arguments → expression ( "," expression )* ;
+    ///
+    /// Stops without consuming `closing`, which may also follow immediately.
+    fn arguments(&mut self, closing: TokenType) -> Vec<Expr> {
+        let mut arguments = vec![];
+
+        if !self.check(closing) {
+            loop {
+                arguments.push(self.expression());
+
+                if !self.match_token([TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        arguments
     }
 
-    /// primary = Number | String | True | False | Nil | "(" expression ")"
+    /// primary = Number | String | True | False | Nil | "(" expression ")" | "[" arguments? "]"
     fn primary(&mut self) -> Option<Expr> {
         if self.match_token([TokenType::INumber]) {
-            let lit = self.previous().clone().literal.unwrap();
-            return Some(Expr::Literal { value: lit });
+            let token = self.previous().clone();
+            return Some(Expr::Literal {
+                value: token.literal.unwrap(),
+                line: token.line,
+            });
         }
 
         if self.match_token([TokenType::FNumber]) {
-            let lit = self.previous().clone().literal.unwrap();
-            return Some(Expr::Literal { value: lit });
+            let token = self.previous().clone();
+            return Some(Expr::Literal {
+                value: token.literal.unwrap(),
+                line: token.line,
+            });
         }
 
         if self.match_token([TokenType::String]) {
-            let lit = self.previous().clone().literal.unwrap();
-            return Some(Expr::Literal { value: lit });
+            let token = self.previous().clone();
+            return Some(Expr::Literal {
+                value: token.literal.unwrap(),
+                line: token.line,
+            });
         }
 
         if self.match_token([TokenType::True]) {
             return Some(Expr::Literal {
                 value: LiteralValue::True,
+                line: self.previous().line,
             });
         }
 
         if self.match_token([TokenType::False]) {
             return Some(Expr::Literal {
                 value: LiteralValue::False,
+                line: self.previous().line,
             });
         }
 
         if self.match_token([TokenType::Nil]) {
             return Some(Expr::Literal {
                 value: LiteralValue::Nil,
+                line: self.previous().line,
             });
         }
 
         if self.match_token([TokenType::LeftParen]) {
             let expr = self.expression();
-            // advance and check if next is right paren
-            if self.advance().token_type == TokenType::RightParen {
-                return Some(Expr::Grouping {
-                    expression: Box::new(expr),
-                });
-            }
-            self.error(self.previous(), "Expecting right paren");
-            return None;
+            self.consume(TokenType::RightParen, "Expecting right paren");
+
+            return Some(Expr::Grouping {
+                expression: Box::new(expr),
+            });
+        }
+
+        if self.match_token([TokenType::LeftBracket]) {
+            let elements = self.arguments(TokenType::RightBracket);
+            self.consume(TokenType::RightBracket, "Expect ']' after list elements");
+
+            return Some(Expr::List { elements });
+        }
+
+        if self.match_token([TokenType::LeftBrace]) {
+            return Some(self.map());
         }
 
         if self.match_token([TokenType::Identifier]) {
             return Some(Expr::Variable {
                 name: self.previous().to_owned(),
+                slot: Slot::default(),
             });
         }
 
         None
     }
 
+    /// map → "{" ( expression ":" expression ( "," expression ":" expression )* )? "}" ;
+    fn map(&mut self) -> Expr {
+        let brace = self.previous().clone();
+        let mut entries = vec![];
+
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                let key = self.expression();
+                self.consume(TokenType::Colon, "Expect ':' after map key");
+                let value = self.expression();
+                entries.push((key, value));
+
+                if !self.match_token([TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries");
+
+        Expr::Map { brace, entries }
+    }
+
+    /// Whether the upcoming `{` opens a map literal, i.e. reads `{ key :`.
+    ///
+    /// Only single-token keys are recognised in statement position; anywhere
+    /// an expression is expected, `{` is always a map.
+    fn map_ahead(&self) -> bool {
+        self.tokens
+            .get(self.current + 2)
+            .is_some_and(|token| token.token_type == TokenType::Colon)
+    }
+
     // fn consume(&mut self, token_types: impl IntoIterator<Item = TokenType>, msg: &str) {
     //     if !self.match_token(token_types) {
     //         ErrorMsg::report(, , )
@@ -479,15 +944,46 @@ impl Parser {
         token
     }
 
+    /// Consumes the `;` ending a statement, which the last statement typed
+    /// at the prompt may leave out.
+    fn end_statement(&mut self, msg: &str) {
+        if !(self.repl && self.is_at_end()) {
+            self.consume(TokenType::Semicolon, msg);
+        }
+    }
+
+    fn check_next(&self, token_type: TokenType) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|token| token.token_type == token_type)
+    }
+
     fn check(&self, token_type: TokenType) -> bool {
         if self.is_at_end() {
             return false;
         }
-        return self.peek().token_type == token_type;
+        self.peek().token_type == token_type
     }
 
     fn error(&self, token: &Token, msg: &str) {
-        ErrorMsg::error(token, msg);
+        if !self.aborted {
+            ErrorMsg::error(token, msg);
+        }
+    }
+
+    /// Opens one more level of nesting, or reports that the input nests too
+    /// deeply and skips to its end. The passes after parsing all recurse on
+    /// the tree, so this is what keeps them off the native stack's limit.
+    fn nest(&mut self) -> bool {
+        self.depth += 1;
+        if self.depth <= MAX_NESTING {
+            return true;
+        }
+
+        self.error(self.peek(), "Too much nesting");
+        self.aborted = true;
+        self.current = self.tokens.len() - 1;
+        false
     }
 
     fn _synchronize(&mut self) {
@@ -502,7 +998,11 @@ impl Parser {
                 self.peek().token_type,
                 TokenType::Class
                     | TokenType::Fun
+                    | TokenType::Throw
+                    | TokenType::Try
                     | TokenType::Var
+                    | TokenType::Const
+                    | TokenType::Let
                     | TokenType::For
                     | TokenType::If
                     | TokenType::While
@@ -516,16 +1016,24 @@ impl Parser {
     }
 }
 
+/// Stands in for an expression that failed to parse.
+fn nil(line: usize) -> Expr {
+    Expr::Literal {
+        value: LiteralValue::Nil,
+        line,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::Parser;
+    use super::{Parser, Stmt};
     use crate::{scanner::Scanner, token::Token};
 
     #[test]
     fn primary_test() {
         let mut parser = Parser::new(vec![Token::new(
             crate::token_type::TokenType::String,
-            Some(crate::token::LiteralValue::StringValue("data".to_string())),
+            Some(crate::token::LiteralValue::StringValue("data".into())),
             "data".to_string(),
             1,
         )]);
@@ -545,4 +1053,114 @@ mod test {
 
         println!("{:?}", parser.expression());
     }
+
+    #[test]
+    fn for_keeps_loop_clauses() {
+        let mut scanner = Scanner::new("for (;;) print 1; for (var i = 0; i < 3; i = i + 1) {}");
+        let stmts = Parser::new(scanner.scan_tokens().to_vec()).parse();
+
+        assert!(matches!(
+            &stmts[0],
+            Stmt::For {
+                initializer: None,
+                condition: None,
+                increment: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &stmts[1],
+            Stmt::For {
+                initializer: Some(_),
+                condition: Some(_),
+                increment: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn var_without_initializer() {
+        let mut scanner = Scanner::new("var a = 1, b, c = 3;");
+        let stmts = Parser::new(scanner.scan_tokens().to_vec()).parse();
+
+        let Stmt::Variable { bindings, .. } = &stmts[0] else {
+            panic!("expected a var declaration, got {:?}", stmts[0]);
+        };
+
+        let names: Vec<_> = bindings.iter().map(|b| b.token.lexeme.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert!(bindings[0].initializer.is_some());
+        assert!(bindings[1].initializer.is_none());
+        assert!(bindings[2].initializer.is_some());
+    }
+
+    #[test]
+    fn pp_statements() {
+        let source = "var a = 1, b; for (;;) { if (a) print a; else { b = 2; } } for (x in xs) {}";
+        let mut scanner = Scanner::new(source);
+        let stmts = Parser::new(scanner.scan_tokens().to_vec()).parse();
+
+        let printed: Vec<_> = stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(
+            printed,
+            [
+                "(var (a 1) b)",
+                "(for _ _ _ (block\n  (if a (print a) (block\n    (expr (= b 2))))))",
+                "(for-in x xs (block))",
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_nesting_stops_parsing() {
+        // Parses on a stack as large as the interpreter's, returning the
+        // statements printed.
+        let parse = |source: String| {
+            std::thread::Builder::new()
+                .stack_size(crate::limits::STACK_SIZE)
+                .spawn(move || {
+                    let mut scanner = Scanner::new(&source);
+                    let stmts = Parser::new(scanner.scan_tokens().to_vec()).parse();
+                    stmts.iter().map(Stmt::to_string).collect::<Vec<_>>()
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+
+        let shallow = format!("print {}1{};", "(".repeat(1000), ")".repeat(1000));
+        assert_eq!(parse(shallow)[0].matches("group").count(), 1000);
+
+        let chain = format!("print 1{};", "+1".repeat(1000));
+        assert_eq!(parse(chain)[0].matches('+').count(), 1000);
+
+        // Would overflow the stack here, and in every pass after parsing.
+        let deep = format!(
+            "print {}1{}; print 2;",
+            "(".repeat(100_000),
+            ")".repeat(100_000)
+        );
+        assert_eq!(parse(deep).len(), 1);
+
+        let long = format!("print 1{};", "+1".repeat(100_000));
+        assert_eq!(parse(long).len(), 1);
+    }
+
+    #[test]
+    fn missing_expression_does_not_panic() {
+        let mut scanner = Scanner::new("print (1; print;");
+        let stmts = Parser::new(scanner.scan_tokens().to_vec()).parse();
+
+        assert_eq!(stmts.len(), 2);
+    }
+
+    #[test]
+    fn repl_allows_missing_final_semicolon() {
+        let mut scanner = Scanner::new("var a = 1; a + 1");
+        let stmts = Parser::new(scanner.scan_tokens().to_vec()).repl().parse();
+
+        assert_eq!(stmts.len(), 2);
+        assert_eq!(stmts[1].to_string(), "(expr (+ a 1))");
+    }
 }
@@ -0,0 +1,254 @@
+//! `--profile`: where the tree walker spends its time.
+//!
+//! `Interpret` tells the profiler when each statement starts and ends. Time
+//! is charged to the statement's line, both inclusive of nested statements
+//! (`total`) and exclusive (`own`). Calls to natives and methods are timed
+//! as well; those happen inside expressions, which have no access to the
+//! interpreter, so they are counted in a thread-local while a profiler is
+//! alive.
+
+use crate::parser::Stmt;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    static CALLS: RefCell<Option<HashMap<String, Calls>>> = const { RefCell::new(None) };
+}
+
+/// Times `f` as a call to the function `name` when a profiler is alive.
+pub fn call<T>(name: impl FnOnce() -> String, f: impl FnOnce() -> T) -> T {
+    if CALLS.with_borrow(Option::is_none) {
+        return f();
+    }
+
+    let name = name();
+    let started = Instant::now();
+    let value = f();
+    let elapsed = started.elapsed();
+
+    CALLS.with_borrow_mut(|calls| {
+        if let Some(calls) = calls {
+            let calls = calls.entry(name).or_default();
+            calls.count += 1;
+            calls.total += elapsed;
+        }
+    });
+    value
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Calls {
+    count: u64,
+    total: Duration,
+}
+
+/// What the statements on one line cost.
+#[derive(Debug, Default, Clone, Copy)]
+struct LineStats {
+    /// Statements started on the line.
+    hits: u64,
+    /// Time from their start to their end.
+    total: Duration,
+    /// `total` less the time spent in statements nested inside them.
+    own: Duration,
+}
+
+// A statement being run.
+struct Frame {
+    // `None` for blocks, whose time belongs to the statement around them.
+    line: Option<usize>,
+    started: Instant,
+    nested: Duration,
+}
+
+pub struct Profiler {
+    source: Vec<String>,
+    stack: Vec<Frame>,
+    lines: BTreeMap<usize, LineStats>,
+    // Own time by the lines of the statements the run was inside, outermost
+    // first.
+    stacks: BTreeMap<Vec<usize>, Duration>,
+    total: Duration,
+}
+
+impl Profiler {
+    /// A profiler for a run of `source`. Calls are timed from here on.
+    pub fn new(source: &str) -> Self {
+        CALLS.set(Some(HashMap::new()));
+
+        Self {
+            source: source.lines().map(str::to_string).collect(),
+            stack: Vec::new(),
+            lines: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            total: Duration::ZERO,
+        }
+    }
+
+    /// Called before `stmt` runs.
+    pub fn enter(&mut self, stmt: &Stmt) {
+        let line = match stmt {
+            Stmt::Block(_) => None,
+            stmt => stmt.line(),
+        };
+        self.stack.push(Frame {
+            line,
+            started: Instant::now(),
+            nested: Duration::ZERO,
+        });
+    }
+
+    /// Called after the statement last entered has finished, however it
+    /// finished.
+    pub fn exit(&mut self) {
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+        let total = frame.started.elapsed();
+
+        match self.stack.last_mut() {
+            Some(parent) => parent.nested += total,
+            None => self.total += total,
+        }
+
+        let Some(line) = frame.line else {
+            // A block's own time goes to the statement around it.
+            if let Some(parent) = self.stack.last_mut() {
+                parent.nested -= total - frame.nested;
+            }
+            return;
+        };
+
+        // In `for (...) print i;` both statements are on one line; count
+        // the time once.
+        let outer = self.stack.iter().any(|outer| outer.line == Some(line));
+        let own = total.saturating_sub(frame.nested);
+        let stats = self.lines.entry(line).or_default();
+        stats.hits += 1;
+        stats.own += own;
+        if !outer {
+            stats.total += total;
+        }
+
+        let mut path: Vec<_> = self.stack.iter().filter_map(|frame| frame.line).collect();
+        path.push(line);
+        path.dedup();
+        *self.stacks.entry(path).or_default() += own;
+    }
+
+    /// A table of the functions called and one of the lines run, each
+    /// sorted by the time spent in it, most first.
+    pub fn report(&self) -> String {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let hits: u64 = self.lines.values().map(|stats| stats.hits).sum();
+        let mut report = format!("[profile] {:.3} ms, {hits} statements\n\n", ms(self.total));
+
+        let mut calls: Vec<_> = CALLS
+            .with_borrow(|calls| calls.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        calls.sort_by(|(a_name, a), (b_name, b)| b.total.cmp(&a.total).then(a_name.cmp(b_name)));
+
+        // Without user functions the script is the only one with a body.
+        let _ = writeln!(
+            report,
+            "{:<20} {:>10} {:>12}",
+            "function", "calls", "total ms"
+        );
+        let _ = writeln!(
+            report,
+            "{:<20} {:>10} {:>12.3}",
+            "script",
+            1,
+            ms(self.total)
+        );
+        for (name, calls) in calls {
+            let _ = writeln!(
+                report,
+                "{name:<20} {:>10} {:>12.3}",
+                calls.count,
+                ms(calls.total)
+            );
+        }
+
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by(|(a_line, a), (b_line, b)| b.own.cmp(&a.own).then(a_line.cmp(b_line)));
+
+        let _ = writeln!(
+            report,
+            "\n{:>6} {:>10} {:>12} {:>12}  source",
+            "line", "hits", "total ms", "self ms"
+        );
+        for (line, stats) in lines {
+            let text = self.source.get(line - 1).map_or("", |text| text.trim());
+            let _ = writeln!(
+                report,
+                "{line:>6} {:>10} {:>12.3} {:>12.3}  {text}",
+                stats.hits,
+                ms(stats.total),
+                ms(stats.own)
+            );
+        }
+        report
+    }
+
+    /// The run as folded stacks, one `script;line 2;line 3 <ns>` per line,
+    /// as flamegraph tools read them. Each count is the own time of the
+    /// last line, in nanoseconds.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(path, own)| {
+                let frames: String = path.iter().map(|line| format!(";line {line}")).collect();
+                format!("script{frames} {}\n", own.as_nanos())
+            })
+            .collect()
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        CALLS.set(None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Profiler;
+    use crate::{
+        interpret::Interpret,
+        testing::{globals, resolve},
+    };
+
+    #[test]
+    fn counts_lines_and_stacks() {
+        let source = "var n = 0;\nfor (i in 0..3) {\n  n = n + [i].len();\n}\n";
+        let stmts = resolve(source);
+
+        let mut interpret = Interpret::new().with_profiler(Profiler::new(source));
+        let env = globals();
+        interpret.interpret(&stmts, env).unwrap();
+        let profiler = interpret.take_profiler().unwrap();
+
+        assert_eq!(profiler.lines[&1].hits, 1);
+        assert_eq!(profiler.lines[&2].hits, 1);
+        assert_eq!(profiler.lines[&3].hits, 3);
+        assert!(profiler.lines[&2].total >= profiler.lines[&3].total);
+
+        let folded: Vec<_> = profiler
+            .folded()
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+            .collect();
+        assert_eq!(
+            folded,
+            ["script;line 1", "script;line 2", "script;line 2;line 3"]
+        );
+        assert!(profiler.report().contains("list.len"));
+    }
+}
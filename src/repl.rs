@@ -0,0 +1,101 @@
+//! Helpers for the interactive prompt.
+
+use std::{env, path::PathBuf};
+
+pub const HELP: &str = "\
+:help          show this list
+:vars          list global variables
+:reset         forget all global variables
+:load <file>   run a script in this session
+:ast <code>    print the syntax tree of <code>
+:time <code>   run <code> and report how long it took
+
+A bare expression prints its value; a missing final ';' is fine.";
+
+/// A line starting with `:`, handled by the prompt itself.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Vars,
+    Reset,
+    Load(PathBuf),
+    Ast(String),
+    Time(String),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let (name, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let argument = argument.trim();
+        let required = || match argument {
+            "" => Err(format!("{name} needs an argument; see :help")),
+            argument => Ok(argument.to_string()),
+        };
+
+        match name {
+            ":help" => Ok(Command::Help),
+            ":vars" => Ok(Command::Vars),
+            ":reset" => Ok(Command::Reset),
+            ":load" => required().map(PathBuf::from).map(Command::Load),
+            ":ast" => required().map(Command::Ast),
+            ":time" => required().map(Command::Time),
+            _ => Err(format!("Unknown command {name}; see :help")),
+        }
+    }
+}
+
+/// Whether `source` stops partway through a statement, so the prompt should
+/// read another line before running it: a bracket, brace or parenthesis is
+/// still open, or a string is unterminated.
+///
+/// Extra closing brackets count as complete; the parser reports them.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut depth = 0i64;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Strings may span lines and have no escapes.
+            '"' if !chars.by_ref().any(|c| c == '"') => return true,
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth > 0
+}
+
+/// `~/.rlox_history`, kept across sessions.
+pub fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rlox_history"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_incomplete, Command};
+
+    #[test]
+    fn detects_unfinished_input() {
+        assert!(is_incomplete("{\n  var a = 1;\n"));
+        assert!(is_incomplete("print (1 +"));
+        assert!(is_incomplete("var s = \"two\nlines"));
+        assert!(!is_incomplete("{ print \"}\"; }\n"));
+        assert!(!is_incomplete("print 1; // (\n"));
+        assert!(!is_incomplete("print 1);"));
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse(":vars"), Ok(Command::Vars));
+        assert_eq!(
+            Command::parse(":time  1 + 2 "),
+            Ok(Command::Time("1 + 2".to_string()))
+        );
+        assert!(Command::parse(":load").is_err());
+        assert!(Command::parse(":quit").is_err());
+    }
+}
@@ -0,0 +1,426 @@
+use crate::{
+    expr::Expr, intern::Symbol, parser::Stmt, token::Token, token_type::TokenType, ErrorMsg,
+};
+use std::{cell::Cell, collections::HashMap};
+
+/// Where the resolver placed a variable: the index of its local slot, or
+/// `None` for a global looked up by name.
+pub type Slot = Cell<Option<usize>>;
+
+struct Declaration {
+    slot: Option<usize>,
+    // Index into `Record::declarations`; `None` for the locals given to
+    // `with_locals`, declared outside the code resolved.
+    id: Option<usize>,
+}
+
+/// Every declaration the resolver saw and every use of one, for tools that
+/// need the program's scoping without working it out again.
+#[derive(Debug, Default)]
+pub struct Record {
+    pub declarations: Vec<Declared>,
+    pub uses: Vec<Use>,
+}
+
+/// A name introduced by `var`, `const`, `let`, `for` or `catch`.
+#[derive(Debug)]
+pub struct Declared {
+    /// The declaring token, which gives the name and where it is.
+    pub name: Token,
+    /// The declaring keyword: `Var`, `Const`, `Let`, `For` or `Catch`.
+    pub kind: TokenType,
+    /// Scopes enclosing the declaration; 0 for a global.
+    pub depth: usize,
+    /// The declaration of the same name visible where this one was made.
+    pub shadows: Option<usize>,
+    /// Declared as the whole body of a branch or loop in a block, where it
+    /// is scoped to that body.
+    pub branch: bool,
+}
+
+impl Declared {
+    pub fn is_global(&self) -> bool {
+        self.depth == 0
+    }
+
+    pub fn is_constant(&self) -> bool {
+        matches!(self.kind, TokenType::Const | TokenType::Let)
+    }
+}
+
+/// A variable read or assigned.
+#[derive(Debug)]
+pub struct Use {
+    pub name: Token,
+    /// Index into `Record::declarations` of the declaration it refers to.
+    pub declaration: usize,
+    pub assigns: bool,
+}
+
+/// Static pass run between parsing and interpretation.
+///
+/// Tracks every declaration visible at each point of the program, assigns
+/// block-scoped variables to slots so the tree walker can reach them by
+/// index, and reports assignments to constants before any code runs. Names
+/// it cannot see (e.g. globals defined by an earlier REPL line) are left to
+/// the runtime check in `Environment::assign`. What it found is kept in a
+/// `Record` for the tools that need it.
+pub struct Resolver {
+    // Innermost scope last; the first one holds the globals.
+    scopes: Vec<HashMap<Symbol, Declaration>>,
+    // Slot handed to the next local declaration.
+    next_slot: usize,
+    had_error: bool,
+    record: Record,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            next_slot: 0,
+            had_error: false,
+            record: Record::default(),
+        }
+    }
+
+    /// A resolver that sees `locals`, each a name and its slot, as declared
+    /// in an enclosing block. Code resolved with it can run in the middle of
+    /// a program, where those locals are live.
+    pub fn with_locals(locals: &[(Symbol, usize)]) -> Self {
+        let scope = locals
+            .iter()
+            .map(|(name, slot)| {
+                let declaration = Declaration {
+                    slot: Some(*slot),
+                    id: None,
+                };
+                (name.clone(), declaration)
+            })
+            .collect();
+
+        Self {
+            scopes: vec![HashMap::new(), scope],
+            next_slot: locals.iter().map(|(_, slot)| slot + 1).max().unwrap_or(0),
+            had_error: false,
+            record: Record::default(),
+        }
+    }
+
+    /// Returns `true` when the program is free of resolution errors.
+    pub fn resolve(self, stmts: &[Stmt]) -> bool {
+        self.record(stmts).0
+    }
+
+    /// `resolve`, also returning the declarations and uses it found. The
+    /// record covers the whole program even when there are errors.
+    pub fn record(mut self, stmts: &[Stmt]) -> (bool, Record) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+
+        (!self.had_error, self.record)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expr(expr),
+
+            Stmt::Variable { keyword, bindings } => {
+                for binding in bindings {
+                    if let Some(initializer) = &binding.initializer {
+                        self.expr(initializer);
+                    }
+
+                    let kind = keyword.token_type.clone();
+                    binding.slot.set(self.declare(&binding.token, kind));
+                }
+            }
+
+            Stmt::Block(stmts) => self.scoped(|resolver| {
+                for stmt in stmts {
+                    resolver.stmt(stmt);
+                }
+            }),
+
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.nested(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.nested(else_branch);
+                }
+            }
+
+            Stmt::While { expr, stmt } => {
+                self.expr(expr);
+                self.nested(stmt);
+            }
+
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => self.scoped(|resolver| {
+                if let Some(initializer) = initializer {
+                    resolver.stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    resolver.expr(condition);
+                }
+                if let Some(increment) = increment {
+                    resolver.expr(increment);
+                }
+                resolver.nested(body);
+            }),
+
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                slot,
+                ..
+            } => {
+                self.expr(iterable);
+                self.scoped(|resolver| {
+                    slot.set(resolver.declare(name, TokenType::For));
+                    resolver.stmt(body);
+                });
+            }
+
+            Stmt::Throw { value, .. } => self.expr(value),
+
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.stmt(body);
+                if let Some(catch) = catch {
+                    self.scoped(|resolver| {
+                        catch
+                            .slot
+                            .set(resolver.declare(&catch.name, TokenType::Catch));
+                        resolver.stmt(&catch.body);
+                    });
+                }
+                if let Some(finally) = finally {
+                    self.stmt(finally);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+
+            Expr::Unary { expression, .. } | Expr::Grouping { expression } => self.expr(expression),
+
+            Expr::Assign { name, value, slot } => {
+                self.expr(value);
+                slot.set(self.refer(name, true));
+
+                let constant = self
+                    .lookup(&name.lexeme)
+                    .and_then(|declaration| declaration.id)
+                    .map(|id| &self.record.declarations[id])
+                    .filter(|declared| declared.is_constant());
+                if let Some(constant) = constant {
+                    ErrorMsg::error(
+                        name,
+                        &format!(
+                            "Cannot assign to constant '{}' declared on line {}",
+                            name.lexeme, constant.name.line
+                        ),
+                    );
+                    self.had_error = true;
+                }
+            }
+
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+
+            Expr::Range { start, end, .. } => {
+                self.expr(start);
+                self.expr(end);
+            }
+
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.expr(index);
+            }
+
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+
+            Expr::Get { object, .. } => self.expr(object),
+
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+            }
+
+            Expr::Variable { name, slot } => slot.set(self.refer(name, false)),
+
+            Expr::Literal { .. } => {}
+        }
+    }
+
+    /// Resolves a branch or loop body that is not necessarily a block.
+    ///
+    /// Inside a block, a bare declaration there is scoped to the body, as
+    /// the compiler does, so no name is declared on only one path. At the
+    /// top level it is a global either way.
+    fn nested(&mut self, stmt: &Stmt) {
+        if self.scopes.len() == 1 {
+            return self.stmt(stmt);
+        }
+
+        let first = self.record.declarations.len();
+        self.scoped(|resolver| resolver.stmt(stmt));
+        if let Stmt::Variable { .. } = stmt {
+            for declared in &mut self.record.declarations[first..] {
+                declared.branch = true;
+            }
+        }
+    }
+
+    /// Runs `f` in a new block scope, releasing its slots afterwards.
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        let next_slot = self.next_slot;
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+        self.next_slot = next_slot;
+    }
+
+    /// Records `name`, declared by the keyword `kind`, in the innermost
+    /// scope and returns its slot, or `None` at the top level where it is
+    /// a global.
+    fn declare(&mut self, name: &Token, kind: TokenType) -> Option<usize> {
+        let slot = (self.scopes.len() > 1).then(|| {
+            self.next_slot += 1;
+            self.next_slot - 1
+        });
+
+        let id = self.record.declarations.len();
+        let shadows = self
+            .lookup(&name.lexeme)
+            .and_then(|declaration| declaration.id);
+        self.record.declarations.push(Declared {
+            name: name.clone(),
+            kind,
+            depth: self.scopes.len() - 1,
+            shadows,
+            branch: false,
+        });
+
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("global scope is never popped");
+        let declaration = Declaration { slot, id: Some(id) };
+        scope.insert(name.lexeme.to_owned(), declaration);
+
+        slot
+    }
+
+    /// Records a use of `name` and returns the slot it refers to, or `None`
+    /// for a global.
+    fn refer(&mut self, name: &Token, assigns: bool) -> Option<usize> {
+        let declaration = self.lookup(&name.lexeme)?;
+        let slot = declaration.slot;
+
+        if let Some(id) = declaration.id {
+            self.record.uses.push(Use {
+                name: name.clone(),
+                declaration: id,
+                assigns,
+            });
+        }
+
+        slot
+    }
+
+    fn lookup(&self, name: &Symbol) -> Option<&Declaration> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resolver;
+    use crate::testing::parse;
+
+    fn resolves(source: &str) -> bool {
+        let stmts = parse(source);
+        Resolver::new().resolve(&stmts)
+    }
+
+    #[test]
+    fn rejects_constant_reassignment() {
+        assert!(!resolves("const a = 1; a = 2;"));
+        assert!(!resolves("let a = 1; { a = 2; }"));
+        assert!(resolves("const a = 1; { var a = 2; a = 3; }"));
+        assert!(resolves("var a = 1; a = 2;"));
+    }
+
+    #[test]
+    fn records_declarations_and_uses() {
+        let stmts = parse("const a = 1;\n{\n  if (a) var a = 2;\n  var b; b = a;\n}");
+        let (resolved, record) = Resolver::new().record(&stmts);
+        assert!(resolved);
+
+        let declared: Vec<_> = record
+            .declarations
+            .iter()
+            .map(|d| (d.name.line, d.is_constant(), d.depth, d.shadows, d.branch))
+            .collect();
+        assert_eq!(
+            declared,
+            [
+                (1, true, 0, None, false),
+                (3, false, 2, Some(0), true),
+                (4, false, 1, None, false),
+            ]
+        );
+
+        let uses: Vec<_> = record
+            .uses
+            .iter()
+            .map(|u| (u.name.line, u.declaration, u.assigns))
+            .collect();
+        assert_eq!(uses, [(3, 0, false), (4, 0, false), (4, 2, true)]);
+    }
+}
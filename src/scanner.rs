@@ -9,41 +9,67 @@ use crate::{
 };
 
 lazy_static! {
-    static ref KEYWORDS: HashMap<&'static str, TokenType> = {
+    pub static ref KEYWORDS: HashMap<&'static str, TokenType> = {
         HashMap::from_iter([
             ("and", TokenType::And),
+            ("catch", TokenType::Catch),
             ("class", TokenType::Class),
+            ("const", TokenType::Const),
             ("else", TokenType::Else),
             ("false", TokenType::False),
+            ("finally", TokenType::Finally),
             ("for", TokenType::For),
             ("fun", TokenType::Fun),
             ("if", TokenType::If),
+            ("in", TokenType::In),
+            ("let", TokenType::Let),
             ("nil", TokenType::Nil),
             ("or", TokenType::Or),
             ("print", TokenType::Print),
             ("return", TokenType::Return),
             ("super", TokenType::Super),
             ("this", TokenType::This),
+            ("throw", TokenType::Throw),
             ("true", TokenType::True),
+            ("try", TokenType::Try),
             ("var", TokenType::Var),
             ("while", TokenType::While),
         ])
     };
 }
 
+/// A `//` comment. The parser never sees these; they are kept for tools
+/// that rewrite source, like the formatter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// From `//` up to the end of the line, trailing whitespace removed.
+    pub text: String,
+    pub line: usize,
+    /// Index of the token that follows the comment.
+    pub next_token: usize,
+    /// Nothing but whitespace precedes the comment on its line.
+    pub own_line: bool,
+}
+
 #[derive(Default)]
 pub struct Scanner<'a> {
     source: &'a str,
     pub tokens: Vec<Token>,
+    pub comments: Vec<Comment>,
     start: usize,
     current: usize,
     line: usize,
+    // Byte offset where the current line begins.
+    line_start: usize,
+    // Column of the token being scanned.
+    column: usize,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
         Scanner {
             source,
+            line: 1,
             ..Default::default()
         }
     }
@@ -51,12 +77,13 @@ impl<'a> Scanner<'a> {
     fn add_token(&mut self, token: TokenType, literal: Option<LiteralValue>) {
         let lexeme = &self.source.as_bytes()[self.start..self.current];
 
-        let t = Token::new(
+        let mut t = Token::new(
             token,
             literal,
             String::from_utf8(lexeme.to_vec()).unwrap(),
             self.line,
         );
+        t.column = self.column;
 
         self.tokens.push(t)
     }
@@ -66,11 +93,13 @@ impl<'a> Scanner<'a> {
     pub fn scan_tokens(&mut self) -> &[Token] {
         while !self.is_at_end() {
             self.start = self.current;
+            self.column = self.start - self.line_start + 1;
             self.scan_token();
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Eof, None, "".to_string(), self.line));
+        let mut eof = Token::new(TokenType::Eof, None, "".to_string(), self.line);
+        eof.column = self.current - self.line_start + 1;
+        self.tokens.push(eof);
 
         &self.tokens
     }
@@ -86,7 +115,21 @@ impl<'a> Scanner<'a> {
             '}' => self.add_token(TokenType::RightBrace, None),
             '(' => self.add_token(TokenType::LeftParen, None),
             ')' => self.add_token(TokenType::RightParen, None),
+            '[' => self.add_token(TokenType::LeftBracket, None),
+            ']' => self.add_token(TokenType::RightBracket, None),
+            ':' => self.add_token(TokenType::Colon, None),
             ',' => self.add_token(TokenType::Comma, None),
+            '.' => {
+                if self.match_char('.') {
+                    if self.match_char('=') {
+                        self.add_token(TokenType::DotDotEqual, None)
+                    } else {
+                        self.add_token(TokenType::DotDot, None)
+                    }
+                } else {
+                    self.add_token(TokenType::Dot, None)
+                }
+            }
             '-' => self.add_token(TokenType::Minus, None),
             '+' => self.add_token(TokenType::Plus, None),
             ';' => self.add_token(TokenType::Semicolon, None),
@@ -124,6 +167,13 @@ impl<'a> Scanner<'a> {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+
+                    self.comments.push(Comment {
+                        text: self.source[self.start..self.current].trim_end().to_string(),
+                        line: self.line,
+                        next_token: self.tokens.len(),
+                        own_line: self.tokens.last().is_none_or(|t| t.line != self.line),
+                    });
                 } else {
                     self.add_token(TokenType::Slash, None)
                 }
@@ -131,6 +181,7 @@ impl<'a> Scanner<'a> {
             ' ' | '\r' | '\t' => {}
             '\n' => {
                 self.line += 1;
+                self.line_start = self.current;
             }
             '"' => self.handle_string(),
             '0'..='9' => {
@@ -140,10 +191,7 @@ impl<'a> Scanner<'a> {
                 self.identifier();
             }
 
-            _ => {
-                // ErrorMsg::error(self.line, &format!("Unexpected char >> {}", c));
-                println!("Line {} Unexpected char >> {}", self.line, c);
-            }
+            _ => ErrorMsg::report(self.line, "", &format!("Unexpected character '{c}'")),
         }
     }
 
@@ -161,7 +209,7 @@ impl<'a> Scanner<'a> {
 
         self.add_token(
             token_type,
-            Some(LiteralValue::IdentifierValue(lit_text.to_string())),
+            Some(LiteralValue::IdentifierValue(lit_text.into())),
         );
     }
 
@@ -186,13 +234,18 @@ impl<'a> Scanner<'a> {
             let lit_type = LiteralValue::FValue(number.parse::<f64>().unwrap());
             self.add_token(TokenType::FNumber, Some(lit_type))
         } else {
-            let lit_type = LiteralValue::IntValue(number.parse::<i64>().unwrap());
-            self.add_token(TokenType::INumber, Some(lit_type))
+            // Only digits, so parsing fails on overflow alone. The token is
+            // kept either way so the parser reports nothing further.
+            let value = number.parse::<i64>().unwrap_or_else(|_| {
+                ErrorMsg::report(self.line, "", "Integer literal too large.");
+                0
+            });
+            self.add_token(TokenType::INumber, Some(LiteralValue::IntValue(value)))
         }
     }
 
     fn is_digit(&self, c: char) -> bool {
-        c.is_digit(10)
+        c.is_ascii_digit()
     }
 
     fn peek(&self) -> char {
@@ -231,19 +284,21 @@ impl<'a> Scanner<'a> {
 
         self.current += 1;
 
-        c as char
+        c
     }
 
     fn handle_string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
 
         if self.is_at_end() {
             ErrorMsg::report(self.line, "", "Unterminated string");
+            return;
         }
 
         self.advance();
@@ -251,7 +306,7 @@ impl<'a> Scanner<'a> {
         let sub_str = &self.source[self.start + 1..self.current - 1];
         self.add_token(
             TokenType::String,
-            Some(LiteralValue::StringValue(sub_str.to_string())),
+            Some(LiteralValue::StringValue(sub_str.into())),
         )
     }
 }
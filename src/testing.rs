@@ -0,0 +1,55 @@
+//! Helpers shared by the unit tests.
+
+use crate::{
+    chunk::Chunk,
+    compiler::Compiler,
+    interpret::{Environment, Interpret},
+    parser::{Parser, Stmt},
+    resolver::Resolver,
+    scanner::Scanner,
+    vm::Vm,
+};
+use std::{cell::RefCell, rc::Rc};
+
+pub fn parse(source: &str) -> Vec<Stmt> {
+    let mut scanner = Scanner::new(source);
+    Parser::new(scanner.scan_tokens().to_vec()).parse()
+}
+
+/// Parses `source` and resolves it for the tree walker, which must succeed.
+pub fn resolve(source: &str) -> Vec<Stmt> {
+    let stmts = parse(source);
+    assert!(Resolver::new().resolve(&stmts), "{source}");
+    stmts
+}
+
+pub fn compile(source: &str) -> Chunk {
+    Compiler::new().compile(&parse(source)).unwrap()
+}
+
+pub fn globals() -> Rc<RefCell<Environment>> {
+    Rc::new(RefCell::new(Environment::new()))
+}
+
+/// Runs `source` on the tree walker and prints the global `result`, or the
+/// runtime error.
+pub fn eval(source: &str) -> String {
+    let env = globals();
+    match Interpret::new().interpret(&resolve(source), env.clone()) {
+        Ok(()) => result(&env),
+        Err(error) => error.to_string(),
+    }
+}
+
+/// `eval` on the VM, with the line of a runtime error.
+pub fn eval_vm(source: &str) -> String {
+    let env = globals();
+    match Vm::new().interpret(&compile(source), env.clone()) {
+        Ok(()) => result(&env),
+        Err(error) => format!("[line {}] {}", error.line(), error),
+    }
+}
+
+fn result(env: &Rc<RefCell<Environment>>) -> String {
+    env.borrow().get(&"result".into()).unwrap().to_string()
+}
@@ -1,34 +1,235 @@
-use crate::token_type::TokenType;
+use crate::{intern::Symbol, list::List, map::Map, token_type::TokenType};
+use std::{collections::HashSet, fmt, mem, rc::Rc};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum LiteralValue {
     IntValue(i64),
     FValue(f64),
-    StringValue(String),
-    IdentifierValue(String),
+    StringValue(Symbol),
+    IdentifierValue(Symbol),
+    List(List),
+    Map(Map),
+    Range {
+        start: i64,
+        end: i64,
+        inclusive: bool,
+    },
     True,
     False,
     Nil,
 }
 
-impl ToString for LiteralValue {
-    fn to_string(&self) -> String {
-        match self {
-            LiteralValue::IntValue(i) => i.to_string(),
-            LiteralValue::FValue(f) => f.to_string(),
-            LiteralValue::StringValue(s) => s.to_string(),
-            LiteralValue::IdentifierValue(i) => i.to_string(),
-            LiteralValue::True => "true".to_string(),
-            LiteralValue::False => "false".to_string(),
-            LiteralValue::Nil => "nil".to_string(),
+/// Lists and maps compare by contents. A list or map is always equal to
+/// itself, and a cycle compares equal once both sides have come back
+/// round to a pair already compared.
+///
+/// Data can nest far deeper than the native stack allows, so pairs of
+/// containers wait on an explicit stack instead of recursing.
+impl PartialEq for LiteralValue {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending = Vec::new();
+        let mut compared = HashSet::new();
+        if !shallow_equal(self, other, &mut pending, &mut compared) {
+            return false;
+        }
+
+        while let Some((a, b)) = pending.pop() {
+            let mut equal = |a: &LiteralValue, b: &LiteralValue| {
+                shallow_equal(a, b, &mut pending, &mut compared)
+            };
+            let same = match (&a, &b) {
+                (LiteralValue::List(a), LiteralValue::List(b)) => {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+                }
+                (LiteralValue::Map(a), LiteralValue::Map(b)) => {
+                    a.borrow().equal(&b.borrow(), equal)
+                }
+                _ => unreachable!("only containers are pending"),
+            };
+            if !same {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Compares `a` and `b` without looking inside lists and maps. A pair of
+/// those is queued on `pending` the first time it is seen and assumed
+/// equal meanwhile; any difference inside shows up when it is compared.
+fn shallow_equal(
+    a: &LiteralValue,
+    b: &LiteralValue,
+    pending: &mut Vec<(LiteralValue, LiteralValue)>,
+    compared: &mut HashSet<(*const (), *const ())>,
+) -> bool {
+    let pair = match (a, b) {
+        (LiteralValue::List(x), LiteralValue::List(y)) if !Rc::ptr_eq(x, y) => {
+            (Rc::as_ptr(x) as *const (), Rc::as_ptr(y) as *const ())
+        }
+        (LiteralValue::Map(x), LiteralValue::Map(y)) if !Rc::ptr_eq(x, y) => {
+            (Rc::as_ptr(x) as *const (), Rc::as_ptr(y) as *const ())
         }
+        (LiteralValue::List(_), LiteralValue::List(_))
+        | (LiteralValue::Map(_), LiteralValue::Map(_)) => return true,
+        (LiteralValue::IntValue(a), LiteralValue::IntValue(b)) => return a == b,
+        (LiteralValue::FValue(a), LiteralValue::FValue(b)) => return a == b,
+        (LiteralValue::StringValue(a), LiteralValue::StringValue(b))
+        | (LiteralValue::IdentifierValue(a), LiteralValue::IdentifierValue(b)) => return a == b,
+        (
+            LiteralValue::Range {
+                start,
+                end,
+                inclusive,
+            },
+            LiteralValue::Range {
+                start: other_start,
+                end: other_end,
+                inclusive: other_inclusive,
+            },
+        ) => return start == other_start && end == other_end && inclusive == other_inclusive,
+        (LiteralValue::True, LiteralValue::True)
+        | (LiteralValue::False, LiteralValue::False)
+        | (LiteralValue::Nil, LiteralValue::Nil) => return true,
+        _ => return false,
+    };
+
+    if compared.insert(pair) {
+        pending.push((a.clone(), b.clone()));
+    }
+    true
+}
+
+/// Prints lists and maps from an explicit stack, like `eq`, so deeply
+/// nested data cannot overflow the native one.
+impl fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Lists and maps being printed further up; meeting one again means
+        // a cycle, printed as `[...]` or `{...}`.
+        let mut open = HashSet::new();
+        let mut stack = vec![Print::Value(self.clone(), false)];
+
+        while let Some(print) = stack.pop() {
+            let (value, nested) = match print {
+                Print::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Print::Close(address, text) => {
+                    open.remove(&address);
+                    f.write_str(text)?;
+                    continue;
+                }
+                Print::Value(value, nested) => (value, nested),
+            };
+
+            match &value {
+                // Strings inside a collection are quoted so `["1"]` and
+                // `[1]` print differently.
+                LiteralValue::StringValue(s) if nested => write!(f, "{s:?}")?,
+                LiteralValue::List(items) => {
+                    let address = Rc::as_ptr(items) as *const ();
+                    if !open.insert(address) {
+                        f.write_str("[...]")?;
+                        continue;
+                    }
+
+                    f.write_str("[")?;
+                    stack.push(Print::Close(address, "]"));
+                    for (i, item) in items.borrow().iter().enumerate().rev() {
+                        stack.push(Print::Value(item.clone(), true));
+                        if i > 0 {
+                            stack.push(Print::Text(", "));
+                        }
+                    }
+                }
+                LiteralValue::Map(map) => {
+                    let address = Rc::as_ptr(map) as *const ();
+                    if !open.insert(address) {
+                        f.write_str("{...}")?;
+                        continue;
+                    }
+
+                    f.write_str("{")?;
+                    stack.push(Print::Close(address, "}"));
+                    for (i, (key, value)) in map.borrow().entries().iter().enumerate().rev() {
+                        stack.push(Print::Value(value.clone(), true));
+                        stack.push(Print::Text(": "));
+                        stack.push(Print::Value(key.clone(), true));
+                        if i > 0 {
+                            stack.push(Print::Text(", "));
+                        }
+                    }
+                }
+                LiteralValue::IntValue(i) => write!(f, "{i}")?,
+                LiteralValue::FValue(v) => write!(f, "{v}")?,
+                LiteralValue::StringValue(s) => write!(f, "{s}")?,
+                LiteralValue::IdentifierValue(i) => write!(f, "{i}")?,
+                LiteralValue::Range {
+                    start,
+                    end,
+                    inclusive,
+                } => {
+                    let operator = if *inclusive { "..=" } else { ".." };
+                    write!(f, "{start}{operator}{end}")?
+                }
+                LiteralValue::True => f.write_str("true")?,
+                LiteralValue::False => f.write_str("false")?,
+                LiteralValue::Nil => f.write_str("nil")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// What is left to print of a value.
+enum Print {
+    // A value, and whether it sits inside a list or map.
+    Value(LiteralValue, bool),
+    Text(&'static str),
+    // The end of the list or map at the address.
+    Close(*const (), &'static str),
+}
+
+/// Dropping the last reference to a list or map would drop its elements
+/// recursively, overflowing the native stack on deeply nested data.
+/// Instead the elements of every container freed along the way are moved
+/// onto one work list.
+impl Drop for LiteralValue {
+    fn drop(&mut self) {
+        let mut owned = Vec::new();
+        take_contents(self, &mut owned);
+        while let Some(mut value) = owned.pop() {
+            // Emptied here, `value` frees nothing nested when it drops.
+            take_contents(&mut value, &mut owned);
+        }
+    }
+}
+
+// Moves out the contents of a list or map nothing else refers to.
+fn take_contents(value: &mut LiteralValue, owned: &mut Vec<LiteralValue>) {
+    match value {
+        LiteralValue::List(list) if Rc::strong_count(list) == 1 => {
+            owned.append(&mut list.borrow_mut());
+        }
+        LiteralValue::Map(map) if Rc::strong_count(map) == 1 => {
+            let entries = mem::take(&mut *map.borrow_mut()).into_entries();
+            owned.extend(entries.into_iter().flat_map(|(key, value)| [key, value]));
+        }
+        _ => {}
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub line: usize,
+    /// Byte column of the first character, counting from 1; 0 for tokens
+    /// made up after scanning.
+    pub column: usize,
     pub literal: Option<LiteralValue>,
     pub token_type: TokenType,
 }
@@ -37,14 +238,27 @@ impl Token {
     pub fn new(
         token_type: TokenType,
         literal: Option<LiteralValue>,
-        lexeme: String,
+        lexeme: impl Into<Symbol>,
         line: usize,
     ) -> Token {
         Self {
             token_type,
-            lexeme,
+            lexeme: lexeme.into(),
             literal,
             line,
+            column: 0,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let token_type = format!("{:?}", self.token_type);
+        write!(f, "{:4} {:<14} '{}'", self.line, token_type, self.lexeme)?;
+
+        match &self.literal {
+            Some(LiteralValue::IdentifierValue(_)) | None => Ok(()),
+            Some(literal) => write!(f, " {}", literal),
         }
     }
 }
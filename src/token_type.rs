@@ -5,6 +5,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -14,6 +17,8 @@ pub enum TokenType {
     Star,
 
     // ONE OR TWO CHARACTER TOKENS.
+    DotDot,
+    DotDotEqual,
     Bang,
     BangEqual,
     Equal,
@@ -31,19 +36,26 @@ pub enum TokenType {
 
     // KEYWORDS.
     And,
+    Catch,
     Class,
+    Const,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    In,
+    Let,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
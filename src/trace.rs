@@ -0,0 +1,106 @@
+//! Call frames, kept by both backends so a runtime error is reported with
+//! the calls that led to it.
+
+use crate::{error::LoxError, intern::Symbol};
+use std::fmt;
+
+/// A call in progress.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The function running, or `None` for the top-level script.
+    pub function: Option<Symbol>,
+    /// Line of the call that entered the frame; `None` for the script.
+    pub called_from: Option<usize>,
+}
+
+impl Frame {
+    /// The frame every run starts in. Without function calls it is also
+    /// the only one.
+    pub fn script() -> Self {
+        Self {
+            function: None,
+            called_from: None,
+        }
+    }
+}
+
+/// A runtime error followed by its stack trace, one `[line N] in <frame>`
+/// line per frame, innermost first.
+pub struct Trace<'a> {
+    error: &'a LoxError,
+    // The frames active when `error` was raised, outermost first.
+    frames: &'a [Frame],
+}
+
+impl<'a> Trace<'a> {
+    pub fn new(error: &'a LoxError, frames: &'a [Frame]) -> Self {
+        Self { error, frames }
+    }
+}
+
+impl fmt::Display for Trace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error {
+            LoxError::Thrown { value, .. } => writeln!(f, "Uncaught exception: {value}")?,
+            error => writeln!(f, "RuntimeError: {error}")?,
+        }
+
+        // The innermost frame is at the error; every other one is at the
+        // call into the frame inside it.
+        let mut line = self.error.line();
+        for frame in self.frames.iter().rev() {
+            match &frame.function {
+                Some(function) => writeln!(f, "[line {line}] in {function}()")?,
+                None => writeln!(f, "[line {line}] in script")?,
+            }
+            line = frame.called_from.unwrap_or(line);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Frame, Trace};
+    use crate::{
+        error::LoxError,
+        interpret::Interpret,
+        testing::{compile, globals, resolve},
+        token::LiteralValue,
+        vm::Vm,
+    };
+
+    #[test]
+    fn failed_runs_keep_their_frames() {
+        let source = "var xs = [];\nxs.pop();";
+
+        let mut interpret = Interpret::new();
+        assert!(interpret.interpret(&resolve(source), globals()).is_err());
+        assert_eq!(interpret.frames(), [Frame::script()]);
+
+        let mut vm = Vm::new();
+        assert!(vm.interpret(&compile(source), globals()).is_err());
+        assert_eq!(vm.frames(), [Frame::script()]);
+    }
+
+    #[test]
+    fn innermost_frame_first() {
+        let frames = [
+            Frame::script(),
+            Frame {
+                function: Some("fib".into()),
+                called_from: Some(20),
+            },
+        ];
+        let error = LoxError::Thrown {
+            value: LiteralValue::IntValue(1),
+            line: 12,
+        };
+
+        assert_eq!(
+            Trace::new(&error, &frames).to_string(),
+            "Uncaught exception: 1\n[line 12] in fib()\n[line 20] in script\n"
+        );
+    }
+}
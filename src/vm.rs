@@ -0,0 +1,448 @@
+use crate::{
+    chunk::{Chunk, OpCode},
+    error::LoxError,
+    expr,
+    intern::{symbol, Symbol},
+    interpret::Environment,
+    iter::LoxIter,
+    limits::{Budget, Limits},
+    list,
+    map::{self, LoxMap},
+    native,
+    token::{LiteralValue, Token},
+    token_type::TokenType,
+    trace::Frame,
+};
+use std::{cell::RefCell, rc::Rc};
+
+struct CallFrame {
+    ip: usize,
+    // Index of the frame's first stack slot; local slots are relative to it.
+    slots: usize,
+    // What a stack trace reports for the frame.
+    call: Frame,
+}
+
+/// An active `try` clause, pushed by `PushCatch` or `PushFinally`.
+struct Handler {
+    catch: bool,
+    ip: usize,
+    // Heights of `stack`, `iterators` and `pending` when the clause began.
+    stack: usize,
+    iterators: usize,
+    pending: usize,
+}
+
+/// Stack machine executing a compiled `Chunk`.
+///
+/// Globals live in the same `Environment` the tree walker uses, so both
+/// backends see the same REPL state and constants.
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<LiteralValue>,
+    frames: Vec<CallFrame>,
+    // Active `for-in` loops, innermost last.
+    iterators: Vec<LoxIter>,
+    handlers: Vec<Handler>,
+    // Errors set aside while a `finally` clause runs, innermost last.
+    pending: Vec<LoxError>,
+    budget: Budget,
+    // The calls in progress when the last run failed, outermost first.
+    trace: Vec<Frame>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.budget = Budget::new(limits);
+        self
+    }
+
+    pub fn interpret(
+        &mut self,
+        chunk: &Chunk,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        self.budget.start();
+        self.budget.enter()?;
+        self.frames.push(CallFrame {
+            ip: 0,
+            slots: self.stack.len(),
+            call: Frame::script(),
+        });
+
+        let result = self.run(chunk, &env);
+        self.budget.exit();
+        if result.is_err() {
+            // Leave the VM reusable for the next REPL line.
+            self.stack.clear();
+            self.trace = self.frames.drain(..).map(|frame| frame.call).collect();
+            self.iterators.clear();
+            self.handlers.clear();
+            self.pending.clear();
+        }
+
+        result
+    }
+
+    /// The calls in progress when the last run failed, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.trace
+    }
+
+    fn run(&mut self, chunk: &Chunk, env: &Rc<RefCell<Environment>>) -> Result<(), LoxError> {
+        // Every error a handler takes resumes at the handler's code.
+        while let Err(error) = self.dispatch(chunk, env) {
+            self.unwind(error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hands `error` to the innermost `try` clause, or back to the caller
+    /// when there is none or the error cannot be caught.
+    fn unwind(&mut self, error: LoxError) -> Result<(), LoxError> {
+        let handler = match self.handlers.pop() {
+            Some(handler) if error.is_catchable() => handler,
+            _ => return Err(error),
+        };
+
+        self.stack.truncate(handler.stack);
+        self.iterators.truncate(handler.iterators);
+        self.pending.truncate(handler.pending);
+        self.frame_mut().ip = handler.ip;
+
+        if handler.catch {
+            self.push(error.into_value());
+        } else {
+            self.pending.push(error);
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&mut self, chunk: &Chunk, env: &Rc<RefCell<Environment>>) -> Result<(), LoxError> {
+        loop {
+            let offset = self.frame().ip;
+            let line = chunk.line(offset);
+            self.budget.step(Some(line))?;
+            let byte = self.read_byte(chunk);
+            let op = OpCode::try_from(byte).map_err(|byte| {
+                LoxError::runtime(
+                    &token(TokenType::Eof, symbol!(""), line),
+                    format!("Unknown opcode {byte}."),
+                )
+            })?;
+
+            match op {
+                OpCode::Constant => {
+                    let value = self.read_constant(chunk).clone();
+                    self.push(value);
+                }
+                OpCode::Nil => self.push(LiteralValue::Nil),
+                OpCode::True => self.push(LiteralValue::True),
+                OpCode::False => self.push(LiteralValue::False),
+                OpCode::Pop => {
+                    self.pop();
+                }
+
+                OpCode::GetGlobal => {
+                    let name = self.read_name(chunk, line);
+                    let value = env.borrow().read(&name)?;
+                    self.push(value);
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name(chunk, line);
+                    let value = self.pop();
+                    env.borrow_mut().define(name.lexeme, value);
+                }
+                OpCode::DefineConstGlobal => {
+                    let name = self.read_name(chunk, line);
+                    let value = self.pop();
+                    env.borrow_mut().define_constant(&name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name(chunk, line);
+                    let value = self.peek(0).clone();
+                    env.borrow_mut().assign(&name, value)?;
+                }
+
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte(chunk) as usize;
+                    self.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte(chunk) as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+
+                OpCode::Add => self.binary(TokenType::Plus, symbol!("+"), line)?,
+                OpCode::Subtract => self.binary(TokenType::Minus, symbol!("-"), line)?,
+                OpCode::Multiply => self.binary(TokenType::Star, symbol!("*"), line)?,
+                OpCode::Divide => self.binary(TokenType::Slash, symbol!("/"), line)?,
+                OpCode::Greater => self.binary(TokenType::Greater, symbol!(">"), line)?,
+                OpCode::GreaterEqual => {
+                    self.binary(TokenType::GreaterEqual, symbol!(">="), line)?
+                }
+                OpCode::Less => self.binary(TokenType::Less, symbol!("<"), line)?,
+                OpCode::LessEqual => self.binary(TokenType::LessEqual, symbol!("<="), line)?,
+                OpCode::Equal => self.binary(TokenType::EqualEqual, symbol!("=="), line)?,
+                OpCode::NotEqual => self.binary(TokenType::BangEqual, symbol!("!="), line)?,
+
+                OpCode::Negate => {
+                    let value = self.pop();
+                    self.push(expr::unary(
+                        &token(TokenType::Minus, symbol!("-"), line),
+                        value,
+                    )?);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(expr::unary(
+                        &token(TokenType::Bang, symbol!("!"), line),
+                        value,
+                    )?);
+                }
+                OpCode::CheckBool => {
+                    let value = self.peek(0).clone();
+                    expr::boolean(&token(TokenType::And, symbol!("and"), line), value)?;
+                }
+
+                OpCode::Print => println!("{}", self.pop()),
+
+                OpCode::Jump => {
+                    let distance = self.read_u16(chunk);
+                    self.frame_mut().ip += distance as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let distance = self.read_u16(chunk);
+                    if *self.peek(0) != LiteralValue::True {
+                        self.frame_mut().ip += distance as usize;
+                    }
+                }
+                OpCode::Loop => {
+                    let distance = self.read_u16(chunk);
+                    self.frame_mut().ip -= distance as usize;
+                }
+
+                OpCode::BuildList => {
+                    let count = self.read_u16(chunk) as usize;
+                    let values = self.stack.split_off(self.stack.len() - count);
+                    self.push(list::new(values));
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_u16(chunk) as usize;
+                    let values = self.stack.split_off(self.stack.len() - count * 2);
+
+                    let brace = token(TokenType::LeftBrace, symbol!("{"), line);
+                    let mut map = LoxMap::default();
+                    let mut values = values.into_iter();
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        map.insert(key, value, &brace)?;
+                    }
+                    self.push(map::new(map));
+                }
+                OpCode::Range | OpCode::RangeInclusive => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let operator = match op {
+                        OpCode::Range => token(TokenType::DotDot, symbol!(".."), line),
+                        _ => token(TokenType::DotDotEqual, symbol!("..="), line),
+                    };
+                    self.push(expr::range(&operator, start, end)?);
+                }
+                OpCode::Index => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let bracket = token(TokenType::LeftBracket, symbol!("["), line);
+                    self.push(expr::get_index(&bracket, object, index)?);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    let bracket = token(TokenType::LeftBracket, symbol!("["), line);
+                    self.push(expr::set_index(&bracket, object, index, value)?);
+                }
+
+                OpCode::Invoke => {
+                    let name = self.read_name(chunk, line);
+                    let count = self.read_byte(chunk) as usize;
+                    let arguments = self.stack.split_off(self.stack.len() - count);
+                    let object = self.pop();
+                    self.push(expr::invoke(&name, object, arguments)?);
+                }
+                OpCode::CallNative => {
+                    let name = self.read_name(chunk, line);
+                    let count = self.read_byte(chunk) as usize;
+                    let arguments = self.stack.split_off(self.stack.len() - count);
+                    self.push(native::call(&name, arguments)?);
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name(chunk, line);
+                    return Err(LoxError::runtime(
+                        &name,
+                        format!("Method '{}' must be called.", name.lexeme),
+                    ));
+                }
+                OpCode::Call => {
+                    return Err(LoxError::runtime(
+                        &token(TokenType::RightParen, symbol!(")"), line),
+                        "Can only call methods.",
+                    ));
+                }
+
+                OpCode::IterStart => {
+                    let iterable = self.pop();
+                    let keyword = token(TokenType::In, symbol!("in"), line);
+                    self.iterators.push(LoxIter::new(iterable, &keyword)?);
+                }
+                OpCode::IterNext => {
+                    let distance = self.read_u16(chunk);
+                    let next = self.iterators.last_mut().and_then(Iterator::next);
+                    match next {
+                        Some(value) => self.push(value),
+                        None => {
+                            self.iterators.pop();
+                            self.frame_mut().ip += distance as usize;
+                        }
+                    }
+                }
+
+                OpCode::PushCatch | OpCode::PushFinally => {
+                    let distance = self.read_u16(chunk);
+                    self.handlers.push(Handler {
+                        catch: op == OpCode::PushCatch,
+                        ip: self.frame().ip + distance as usize,
+                        stack: self.stack.len(),
+                        iterators: self.iterators.len(),
+                        pending: self.pending.len(),
+                    });
+                }
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    return Err(LoxError::Thrown { value, line });
+                }
+                OpCode::Rethrow => {
+                    return Err(self.pending.pop().expect("no error to rethrow"));
+                }
+
+                OpCode::Return => {
+                    let frame = self.frames.pop().expect("script frame");
+                    self.stack.truncate(frame.slots);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        token_type: TokenType,
+        lexeme: Symbol,
+        line: usize,
+    ) -> Result<(), LoxError> {
+        let right = self.pop();
+        let left = self.pop();
+        let value = expr::binary(&token(token_type, lexeme, line), left, right)?;
+        self.push(value);
+
+        Ok(())
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no active call frame")
+    }
+
+    fn read_byte(&mut self, chunk: &Chunk) -> u8 {
+        let frame = self.frame_mut();
+        frame.ip += 1;
+        chunk.code[frame.ip - 1]
+    }
+
+    fn read_u16(&mut self, chunk: &Chunk) -> u16 {
+        let frame = self.frame_mut();
+        frame.ip += 2;
+        chunk.read_u16(frame.ip - 2)
+    }
+
+    fn read_constant<'c>(&mut self, chunk: &'c Chunk) -> &'c LiteralValue {
+        &chunk.constants[self.read_u16(chunk) as usize]
+    }
+
+    /// Reads a name operand as an identifier token, for lookups and errors.
+    fn read_name(&mut self, chunk: &Chunk, line: usize) -> Token {
+        let name = match self.read_constant(chunk) {
+            LiteralValue::StringValue(name) => name.clone(),
+            constant => constant.to_string().into(),
+        };
+        token(TokenType::Identifier, name, line)
+    }
+
+    fn push(&mut self, value: LiteralValue) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> LiteralValue {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &LiteralValue {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+}
+
+/// Bytecode keeps only line numbers, so errors get a token rebuilt from the
+/// instruction that raised them.
+fn token(token_type: TokenType, lexeme: Symbol, line: usize) -> Token {
+    Token::new(token_type, None, lexeme, line)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::eval_vm;
+
+    #[test]
+    fn locals_and_globals() {
+        assert_eq!(
+            eval_vm("var result = 1; { var a = result + 1; { var a = a * 10; result = a; } }"),
+            "20"
+        );
+        assert_eq!(
+            eval_vm("var result = 0; for (var i = 0; i < 5; i = i + 1) { var sq = i * i; result = result + sq; }"),
+            "30"
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            eval_vm("var result = []; for (x in 1..=3) if (x != 2) result.push(x); else result.push(0);"),
+            "[1, 0, 3]"
+        );
+        assert_eq!(eval_vm("var result = false or (true and 1 < 2);"), "true");
+        assert_eq!(eval_vm("var result = false and 1;"), "false");
+    }
+
+    #[test]
+    fn runtime_errors_keep_line() {
+        assert_eq!(
+            eval_vm("var xs = [1];\nvar result = xs[1];"),
+            "[line 2] Index 1 out of range for list of length 1."
+        );
+        assert_eq!(
+            eval_vm("const k = 1;\n{ var x; }\nk = 2;"),
+            "[line 3] Cannot assign to constant 'k' declared on line 1."
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+fn output<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .expect("failed to run rlox")
+}
+
+/// Runs rlox with `input` on stdin.
+fn piped(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run rlox");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn run(args: &[&str]) -> (String, String) {
+    let output = output(args);
+
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+fn scripts() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts")
+}
+
+/// Paths of every script in `tests/scripts`.
+fn each_script() -> Vec<PathBuf> {
+    fs::read_dir(scripts())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect()
+}
+
+/// A scratch directory for one test, removed again when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rlox-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn join(&self, file: impl AsRef<Path>) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Every script in `tests/scripts` must behave the same under the tree
+/// walker and the bytecode VM, errors included.
+#[test]
+fn vm_matches_tree_walker() {
+    for path in each_script() {
+        let path = path.to_str().unwrap();
+
+        let walker = run(&[path]);
+        let vm = run(&["--vm", path]);
+
+        assert!(!walker.0.is_empty(), "{path} printed nothing");
+        assert_eq!(walker, vm, "{path}");
+    }
+}
+
+/// A script that fails at runtime exits with 70, compiled or not.
+#[test]
+fn runtime_error_exits_70() {
+    let script = scripts().join("runtime_error.lox");
+    let dir = TempDir::new("status");
+    let compiled = dir.join("runtime_error.loxc");
+    run(&[
+        "compile",
+        script.to_str().unwrap(),
+        "-o",
+        compiled.to_str().unwrap(),
+    ]);
+
+    for path in [script.as_path(), compiled.as_path()] {
+        let status = output(&[path]).status;
+        assert_eq!(status.code(), Some(70), "{}", path.display());
+    }
+}
+
+/// Statements nested as deep as the parser allows run on both backends.
+#[test]
+fn deep_statements_run_on_both_backends() {
+    let dir = TempDir::new("deep");
+    let blocks = dir.join("blocks.lox");
+    let branches = dir.join("branches.lox");
+    fs::write(
+        &blocks,
+        format!("{}print 1;{}", "{".repeat(2000), "}".repeat(2000)),
+    )
+    .unwrap();
+    fs::write(&branches, format!("{}print 2;", "if (true) ".repeat(2000))).unwrap();
+
+    for (path, printed) in [(&blocks, "1\n"), (&branches, "2\n")] {
+        let path = path.to_str().unwrap();
+        for args in [vec![path], vec!["--vm", path]] {
+            let output = output(&args);
+            assert!(output.status.success(), "{args:?}");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), printed, "{args:?}");
+        }
+    }
+}
+
+/// Runs every script with and without `flag` on both backends and expects
+/// identical output.
+fn unchanged_by(flag: &str) {
+    for path in each_script() {
+        let path = path.to_str().unwrap();
+
+        assert_eq!(run(&[path]), run(&[flag, path]), "{flag} {path}");
+        assert_eq!(
+            run(&["--vm", path]),
+            run(&["--vm", flag, path]),
+            "--vm {flag} {path}"
+        );
+    }
+}
+
+/// Collecting on every allocation must never free a reachable value.
+#[test]
+fn gc_stress_changes_nothing() {
+    unchanged_by("--gc-stress");
+}
+
+/// Constant folding must keep output and runtime errors intact.
+#[test]
+fn optimizer_changes_nothing() {
+    unchanged_by("-O");
+}
+
+/// Formatting a script must not change what it does, and formatting it
+/// again must not change it further.
+#[test]
+fn formatting_changes_nothing() {
+    let dir = TempDir::new("fmt");
+
+    for path in each_script() {
+        let copy = dir.join(path.file_name().unwrap());
+        fs::copy(&path, &copy).unwrap();
+        let (path, copy) = (path.to_str().unwrap(), copy.to_str().unwrap());
+
+        assert_eq!(
+            run(&["fmt", copy]),
+            (String::new(), String::new()),
+            "{path}"
+        );
+        assert_eq!(run(&[path]), run(&[copy]), "{path}");
+        assert_eq!(
+            run(&["fmt", "--check", copy]),
+            (String::new(), String::new())
+        );
+    }
+}
+
+/// A subcommand without its files prints the usage instead of running a
+/// script by that name.
+#[test]
+fn subcommand_without_files_prints_usage() {
+    for command in ["fmt", "lint", "debug"] {
+        let output = output(&[command]);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        assert_eq!(output.status.code(), Some(64), "{command}");
+        assert!(stderr.starts_with("Usage: rlox"), "{command}: {stderr}");
+    }
+}
+
+/// A whole language server session over stdin and stdout.
+#[test]
+fn lsp_answers_requests() {
+    let messages = [
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#,
+        r#"{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///a.lox", "text": "var a = 1;\nprint a;\nprint (;\n"}}}"#,
+        r#"{"jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": {"textDocument": {"uri": "file:///a.lox"}, "position": {"line": 1, "character": 6}}}"#,
+        r#"{"jsonrpc": "2.0", "id": 3, "method": "shutdown"}"#,
+        r#"{"jsonrpc": "2.0", "method": "exit"}"#,
+    ];
+    let input: String = messages
+        .iter()
+        .map(|body| format!("Content-Length: {}\r\n\r\n{body}", body.len()))
+        .collect();
+
+    let output = piped(&["lsp"], &input);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(stdout.contains(r#""definitionProvider":true"#));
+    assert!(stdout.contains(r#""message":"Expect expression""#));
+    assert!(stdout.contains(
+        r#""id":2,"jsonrpc":"2.0","result":{"range":{"end":{"character":5,"line":0},"start":{"character":4,"line":0}}"#
+    ));
+    assert!(stdout.contains(r#""id":3,"jsonrpc":"2.0","result":null"#));
+}
+
+/// Commands piped to `rlox debug` drive the run.
+#[test]
+fn debugger_follows_commands() {
+    let dir = TempDir::new("debug");
+    let path = dir.join("count.lox");
+    fs::write(
+        &path,
+        "var n = 0;\nfor (i in 0..3) {\n  n = n + i;\n}\nprint n;\n",
+    )
+    .unwrap();
+
+    let input = "b 3\nc\nc\np n\nclear 3\nc\n";
+    let output = piped(&["debug", path.to_str().unwrap()], input);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(stdout.contains("[line 3] n = n + i;"));
+    assert!(stdout.contains("\n0\n"));
+    assert!(stdout.ends_with("3\n"));
+}
+
+/// `--profile` reports to stderr and writes folded stacks on request.
+#[test]
+fn profile_reports_lines() {
+    let dir = TempDir::new("profile");
+    let script = dir.join("loop.lox");
+    let folded = dir.join("loop.folded");
+    fs::write(
+        &script,
+        "var xs = [];\nfor (i in 0..10) {\n  xs.push(i);\n}\n",
+    )
+    .unwrap();
+
+    let (stdout, stderr) = run(&[
+        "--profile",
+        &format!("--profile-folded={}", folded.display()),
+        script.to_str().unwrap(),
+    ]);
+
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("list.push"));
+    assert!(stderr
+        .lines()
+        .any(|line| line.trim_start().starts_with("3 ") && line.ends_with("xs.push(i);")));
+    let folded = fs::read_to_string(&folded).unwrap();
+    assert!(folded.contains("script;line 2;line 3 "));
+}
+
+/// Each run with `--coverage-file` appends one lcov record.
+#[test]
+fn coverage_appends_lcov_records() {
+    let dir = TempDir::new("coverage");
+    let lcov = dir.join("lcov.info");
+    let flag = format!("--coverage-file={}", lcov.display());
+
+    let first = dir.join("first.lox");
+    fs::write(&first, "var n = 1;\nif (n > 1) {\n  print n;\n}\n").unwrap();
+    let second = dir.join("second.lox");
+    fs::write(&second, "throw 1;\nprint 2;\n").unwrap();
+    run(&[&flag, first.to_str().unwrap()]);
+    run(&[&flag, second.to_str().unwrap()]);
+
+    let lcov = fs::read_to_string(&lcov).unwrap();
+    let records: Vec<_> = lcov.split_terminator("end_of_record\n").collect();
+    assert_eq!(records.len(), 2);
+    assert!(records[0].contains("first.lox\n"));
+    assert!(records[0].contains("BRDA:2,0,0,0\nBRDA:2,0,1,1\n"));
+    assert!(records[0].contains("DA:2,1\nDA:3,0\nLF:3\nLH:2\n"));
+    assert!(records[1].contains("second.lox\n"));
+    assert!(records[1].contains("DA:1,1\nDA:2,0\n"));
+}